@@ -0,0 +1,300 @@
+use crate::transfer::Submission;
+use crate::*;
+use anyhow::{ensure, Result};
+use std::ffi::CString;
+
+impl ComputeKernel {
+    /// Build a compute pipeline from a SPIR-V shader with entry point `main`. Set 0 has one
+    /// binding for each of `bindings`, in order. `push_constant_size` bytes of push constants are
+    /// available, if nonzero. Up to `max_sets` descriptor sets may be allocated with
+    /// `allocate_set`.
+    pub fn new(
+        core: &SharedCore,
+        spv: &[u8],
+        bindings: &[vk::DescriptorType],
+        push_constant_size: u32,
+        max_sets: u32,
+    ) -> Result<Self> {
+        let layout_bindings = bindings
+            .iter()
+            .enumerate()
+            .map(|(binding, &ty)| {
+                vk::DescriptorSetLayoutBindingBuilder::new()
+                    .binding(binding as u32)
+                    .descriptor_type(ty)
+                    .descriptor_count(1)
+                    .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            })
+            .collect::<Vec<_>>();
+        let create_info =
+            vk::DescriptorSetLayoutCreateInfoBuilder::new().bindings(&layout_bindings);
+        let descriptor_set_layout = unsafe {
+            core.device
+                .create_descriptor_set_layout(&create_info, None, None)
+        }
+        .result()?;
+
+        let mut kernel = Self {
+            pipeline: vk::Pipeline::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+            descriptor_set_layout,
+            descriptor_pool: vk::DescriptorPool::null(),
+            bindings: bindings.to_vec(),
+            push_constant_size,
+            _core: core.clone(),
+        };
+
+        // On failure, whatever has been created so far is destroyed by drop
+        kernel.build(spv, max_sets)?;
+        Ok(kernel)
+    }
+
+    fn build(&mut self, spv: &[u8], max_sets: u32) -> Result<()> {
+        let core = &self._core;
+
+        let mut pool_sizes: Vec<vk::DescriptorPoolSizeBuilder> = Vec::new();
+        for &ty in &self.bindings {
+            match pool_sizes.iter_mut().find(|size| size._type == ty) {
+                Some(size) => size.descriptor_count += max_sets,
+                None => pool_sizes.push(
+                    vk::DescriptorPoolSizeBuilder::new()
+                        ._type(ty)
+                        .descriptor_count(max_sets),
+                ),
+            }
+        }
+        if !pool_sizes.is_empty() && max_sets > 0 {
+            let create_info = vk::DescriptorPoolCreateInfoBuilder::new()
+                .pool_sizes(&pool_sizes)
+                .max_sets(max_sets);
+            self.descriptor_pool =
+                unsafe { core.device.create_descriptor_pool(&create_info, None, None) }.result()?;
+        }
+
+        let descriptor_set_layouts = [self.descriptor_set_layout];
+        let push_constant_ranges = [vk::PushConstantRangeBuilder::new()
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .offset(0)
+            .size(self.push_constant_size)];
+        let push_constant_ranges = if self.push_constant_size > 0 {
+            &push_constant_ranges[..]
+        } else {
+            &[]
+        };
+        let create_info = vk::PipelineLayoutCreateInfoBuilder::new()
+            .set_layouts(&descriptor_set_layouts)
+            .push_constant_ranges(push_constant_ranges);
+        self.pipeline_layout =
+            unsafe { core.device.create_pipeline_layout(&create_info, None, None) }.result()?;
+
+        let module = crate::material::shader_module(core, spv)?;
+        let entry_point = CString::new("main")?;
+        let stage = vk::PipelineShaderStageCreateInfoBuilder::new()
+            .stage(vk::ShaderStageFlagBits::COMPUTE)
+            .module(module)
+            .name(&entry_point)
+            .build();
+        let create_info = vk::ComputePipelineCreateInfoBuilder::new()
+            .stage(stage)
+            .layout(self.pipeline_layout);
+        let pipelines = unsafe {
+            core.device
+                .create_compute_pipelines(None, &[create_info], None)
+        }
+        .result();
+        unsafe { core.device.destroy_shader_module(Some(module), None) };
+        self.pipeline = pipelines?[0];
+
+        Ok(())
+    }
+
+    /// Allocate a descriptor set from this kernel's pool, binding the whole of each of `buffers`
+    /// to the binding at the same index. Sets are freed along with the kernel.
+    pub fn allocate_set(&self, buffers: &[vk::Buffer]) -> Result<vk::DescriptorSet> {
+        ensure!(
+            buffers.len() == self.bindings.len(),
+            "Expected {} buffers, got {}",
+            self.bindings.len(),
+            buffers.len()
+        );
+        ensure!(
+            !self.descriptor_pool.is_null(),
+            "Kernel has no bindings, or was created with max_sets = 0"
+        );
+
+        let set_layouts = [self.descriptor_set_layout];
+        let allocate_info = vk::DescriptorSetAllocateInfoBuilder::new()
+            .descriptor_pool(self.descriptor_pool)
+            .set_layouts(&set_layouts);
+        let descriptor_set =
+            unsafe { self._core.device.allocate_descriptor_sets(&allocate_info) }.result()?[0];
+
+        let buffer_infos = buffers
+            .iter()
+            .map(|&buffer| {
+                [vk::DescriptorBufferInfoBuilder::new()
+                    .buffer(buffer)
+                    .offset(0)
+                    .range(vk::WHOLE_SIZE)]
+            })
+            .collect::<Vec<_>>();
+        let writes = buffer_infos
+            .iter()
+            .zip(&self.bindings)
+            .enumerate()
+            .map(|(binding, (info, &ty))| {
+                vk::WriteDescriptorSetBuilder::new()
+                    .dst_set(descriptor_set)
+                    .dst_binding(binding as u32)
+                    .descriptor_type(ty)
+                    .buffer_info(info)
+            })
+            .collect::<Vec<_>>();
+        unsafe { self._core.device.update_descriptor_sets(&writes, &[]) };
+
+        Ok(descriptor_set)
+    }
+
+    /// Record a dispatch of `groups` workgroups into `command_buffer`, which must be outside of
+    /// a render pass. `push_constants` must be `push_constant_size` bytes long, or empty if there
+    /// are none. No barriers are recorded; see `compute_barrier`.
+    pub fn record_dispatch(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        descriptor_set: Option<vk::DescriptorSet>,
+        push_constants: &[u8],
+        groups: [u32; 3],
+    ) -> Result<()> {
+        self.check_push_constants(push_constants)?;
+        let device = &self._core.device;
+        unsafe {
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline,
+            );
+            if let Some(descriptor_set) = descriptor_set {
+                device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::COMPUTE,
+                    self.pipeline_layout,
+                    0,
+                    &[descriptor_set],
+                    &[],
+                );
+            }
+            if !push_constants.is_empty() {
+                device.cmd_push_constants(
+                    command_buffer,
+                    self.pipeline_layout,
+                    vk::ShaderStageFlags::COMPUTE,
+                    0,
+                    push_constants.len() as u32,
+                    push_constants.as_ptr() as _,
+                );
+            }
+            device.cmd_dispatch(command_buffer, groups[0], groups[1], groups[2]);
+        }
+        Ok(())
+    }
+
+    /// Dispatch on the utility queue without waiting for completion. Results are made visible to
+    /// the host once the submission completes. Buffers used must be accessible to the utility
+    /// queue family. Buffers in `release` must be exclusively owned by the utility queue family,
+    /// and are released to the graphics queue family; see `Submission::record_acquire`.
+    pub fn dispatch_async(
+        &self,
+        descriptor_set: Option<vk::DescriptorSet>,
+        push_constants: &[u8],
+        groups: [u32; 3],
+        release: &[vk::Buffer],
+    ) -> Result<Submission> {
+        // Check the arguments before anything is submitted
+        self.check_push_constants(push_constants)?;
+        let mut recorded = Ok(());
+        let submission = crate::transfer::submit_releasing(
+            &self._core,
+            release,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::AccessFlags::SHADER_WRITE,
+            |command_buffer| {
+                recorded =
+                    self.record_dispatch(command_buffer, descriptor_set, push_constants, groups);
+                compute_barrier(
+                    &self._core,
+                    command_buffer,
+                    vk::PipelineStageFlags::HOST,
+                    vk::AccessFlags::HOST_READ,
+                );
+            },
+        )?;
+        recorded?;
+        Ok(submission)
+    }
+
+    /// Dispatch on the utility queue and block until it has completed and `release` has been
+    /// acquired by the graphics queue family; see `dispatch_async`
+    pub fn dispatch(
+        &self,
+        descriptor_set: Option<vk::DescriptorSet>,
+        push_constants: &[u8],
+        groups: [u32; 3],
+        release: &[vk::Buffer],
+    ) -> Result<()> {
+        self.dispatch_async(descriptor_set, push_constants, groups, release)?
+            .finish()
+    }
+
+    fn check_push_constants(&self, push_constants: &[u8]) -> Result<()> {
+        ensure!(
+            push_constants.len() as u32 == self.push_constant_size,
+            "Expected {} bytes of push constants, got {}",
+            self.push_constant_size,
+            push_constants.len()
+        );
+        Ok(())
+    }
+
+    /// Name the pipeline and its layouts after `name` for debugging
+    pub fn set_name(&self, name: &str) -> Result<()> {
+        self._core.set_name(self.pipeline, name)?;
+        self._core.set_name(self.pipeline_layout, name)?;
+        self._core.set_name(self.descriptor_set_layout, name)
+    }
+}
+
+impl Drop for ComputeKernel {
+    /// Destroy the pipeline, layouts and descriptor pool. The GPU must be finished using them.
+    fn drop(&mut self) {
+        unsafe {
+            let device = &self._core.device;
+            device.destroy_pipeline(Some(self.pipeline), None);
+            device.destroy_pipeline_layout(Some(self.pipeline_layout), None);
+            device.destroy_descriptor_pool(Some(self.descriptor_pool), None);
+            device.destroy_descriptor_set_layout(Some(self.descriptor_set_layout), None);
+        }
+    }
+}
+
+/// Record a barrier making compute shader writes visible to `dst_access` at `dst_stage`
+pub fn compute_barrier(
+    core: &Core,
+    command_buffer: vk::CommandBuffer,
+    dst_stage: vk::PipelineStageFlags,
+    dst_access: vk::AccessFlags,
+) {
+    let barrier = vk::MemoryBarrierBuilder::new()
+        .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+        .dst_access_mask(dst_access);
+    unsafe {
+        core.device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            dst_stage,
+            None,
+            &[barrier],
+            &[],
+            &[],
+        );
+    }
+}
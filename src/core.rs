@@ -1,8 +1,17 @@
-use anyhow::{format_err, Result};
-use erupt::{utils::loading::DefaultEntryLoader, vk1_0 as vk, DeviceLoader, InstanceLoader};
+use crate::debug::{debug_utils_enabled, messenger_create_info, DebugMessages};
+use crate::windowed::hardware::{
+    check_supported_extensions, intersect_features, missing_features, union_features,
+};
+use crate::{ApplicationInfo, HardwareSelection, VulkanSetup};
+use anyhow::{ensure, format_err, Result};
+use erupt::{
+    extensions::ext_debug_utils::DebugUtilsMessengerEXT, utils::loading::DefaultEntryLoader,
+    vk1_0 as vk, DeviceLoader, ExtendableFrom, InstanceLoader,
+};
 use gpu_alloc::{GpuAllocator, Request};
 use gpu_alloc_erupt::EruptMemoryDevice as EMD;
 
+use std::ffi::{CStr, CString};
 use std::sync::MutexGuard;
 use std::sync::{Arc, Mutex};
 
@@ -12,14 +21,179 @@ pub type Memory = gpu_alloc::MemoryBlock<vk::DeviceMemory>;
 pub struct Core {
     pub utility_queue: vk::Queue,
     pub graphics_queue: vk::Queue,
+    pub utility_queue_family: u32,
+    pub graphics_queue_family: u32,
+    pub physical_device: vk::PhysicalDevice,
+    /// Device features which were enabled; those required by the setup, and those optional
+    /// features which the device supports
+    pub enabled_features: vk::PhysicalDeviceFeatures,
+    /// Device extensions which were enabled, including supported optional extensions
+    pub enabled_extensions: Vec<CString>,
     pub allocator: Mutex<GpuAllocator<vk::DeviceMemory>>,
     pub device: DeviceLoader,
     pub instance: InstanceLoader,
+    /// Forwards validation messages to `log`, if debug utils are enabled
+    pub debug_messenger: Option<DebugUtilsMessengerEXT>,
+    /// Validation errors recorded by the debug messenger; see `Core::check_validation`
+    pub debug_messages: Arc<DebugMessages>,
     pub _entry: DefaultEntryLoader,
 }
 
 impl Core {
-    pub fn allocator(&self) -> Result<MutexGuard<GpuAllocator<vk::DeviceMemory>>> {
+    /// Create a logical device, queues and allocator for the selected hardware. The core takes
+    /// ownership of `instance` and `debug_messenger`, destroying them on failure, and keeps
+    /// `debug_messages` alive until the instance is destroyed.
+    pub fn new(
+        entry: DefaultEntryLoader,
+        instance: InstanceLoader,
+        debug_messenger: Option<DebugUtilsMessengerEXT>,
+        debug_messages: Arc<DebugMessages>,
+        hardware: &HardwareSelection,
+        setup: &VulkanSetup,
+    ) -> Result<SharedCore> {
+        Self::new_with_cleanup(
+            entry,
+            instance,
+            debug_messenger,
+            debug_messages,
+            hardware,
+            setup,
+            |_| (),
+        )
+    }
+
+    /// Like `new`, calling `cleanup` to destroy other children of `instance`, such as a surface,
+    /// before the instance is destroyed on failure
+    pub(crate) fn new_with_cleanup(
+        entry: DefaultEntryLoader,
+        instance: InstanceLoader,
+        debug_messenger: Option<DebugUtilsMessengerEXT>,
+        debug_messages: Arc<DebugMessages>,
+        hardware: &HardwareSelection,
+        setup: &VulkanSetup,
+        cleanup: impl FnOnce(&InstanceLoader),
+    ) -> Result<SharedCore> {
+        let (device, enabled_features, enabled_extensions, device_props) =
+            match Self::create_device(&instance, hardware, setup) {
+                Ok(created) => created,
+                Err(e) => {
+                    cleanup(&instance);
+                    unsafe { destroy_instance(&instance, debug_messenger) };
+                    return Err(e);
+                }
+            };
+
+        // Create queues
+        let graphics_queue =
+            unsafe { device.get_device_queue(hardware.graphics_queue_family, 0, None) };
+        let utility_queue =
+            unsafe { device.get_device_queue(hardware.utility_queue_family, 0, None) };
+
+        // Create allocator
+        let allocator = Mutex::new(GpuAllocator::new(
+            gpu_alloc::Config::i_am_prototyping(),
+            device_props,
+        ));
+
+        Ok(SharedCore::new(Core {
+            utility_queue,
+            graphics_queue,
+            utility_queue_family: hardware.utility_queue_family,
+            graphics_queue_family: hardware.graphics_queue_family,
+            physical_device: hardware.physical_device,
+            enabled_features,
+            enabled_extensions,
+            device,
+            instance,
+            allocator,
+            debug_messenger,
+            debug_messages,
+            _entry: entry,
+        }))
+    }
+
+    /// Create the logical device, returning it along with the features and extensions enabled
+    /// and the properties the allocator needs. Nothing fallible follows device creation, so the
+    /// device is never leaked.
+    fn create_device(
+        instance: &InstanceLoader,
+        hardware: &HardwareSelection,
+        setup: &VulkanSetup,
+    ) -> Result<(
+        DeviceLoader,
+        vk::PhysicalDeviceFeatures,
+        Vec<CString>,
+        gpu_alloc::DeviceProperties<'static>,
+    )> {
+        // Create logical device, with one queue from each distinct family
+        let mut queue_families = vec![
+            hardware.graphics_queue_family,
+            hardware.utility_queue_family,
+        ];
+        queue_families.dedup();
+        let create_info = queue_families
+            .iter()
+            .map(|&family| {
+                vk::DeviceQueueCreateInfoBuilder::new()
+                    .queue_family_index(family)
+                    .queue_priorities(&[1.0])
+            })
+            .collect::<Vec<_>>();
+
+        // Enable required features and extensions, plus whichever optional ones are supported
+        let supported_features =
+            unsafe { instance.get_physical_device_features(hardware.physical_device, None) };
+        let missing = missing_features(&setup.required_features, &supported_features);
+        ensure!(
+            missing.is_empty(),
+            "Missing required device features: {}",
+            missing.join(", ")
+        );
+        let enabled_features = union_features(
+            &setup.required_features,
+            &intersect_features(&setup.optional_features, &supported_features),
+        );
+
+        let supported_extensions: Vec<CString> = check_supported_extensions(
+            instance,
+            hardware.physical_device,
+            &setup.device_extensions,
+        )?
+        .iter()
+        .map(|properties| unsafe { CStr::from_ptr(properties.extension_name.as_ptr()) }.to_owned())
+        .collect();
+        let mut extension_names = setup.device_extensions.clone();
+        for &name in &setup.optional_device_extensions {
+            let extension = unsafe { CStr::from_ptr(name) };
+            if contains_name(&supported_extensions, extension) {
+                extension_names.push(name);
+            } else {
+                log::info!("Optional device extension {:?} is not supported", extension);
+            }
+        }
+        let enabled_extensions = extension_names
+            .iter()
+            .map(|&name| unsafe { CStr::from_ptr(name) }.to_owned())
+            .collect();
+
+        let create_info = vk::DeviceCreateInfoBuilder::new()
+            .queue_create_infos(&create_info)
+            .enabled_features(&enabled_features)
+            .enabled_extension_names(&extension_names)
+            .enabled_layer_names(&setup.device_layers);
+
+        let device_props =
+            unsafe { gpu_alloc_erupt::device_properties(instance, hardware.physical_device)? };
+        let device = DeviceLoader::new(instance, hardware.physical_device, &create_info, None)?;
+        Ok((device, enabled_features, enabled_extensions, device_props))
+    }
+
+    /// Whether a device extension was enabled
+    pub fn extension_enabled(&self, name: &CStr) -> bool {
+        contains_name(&self.enabled_extensions, name)
+    }
+
+    pub fn allocator(&self) -> Result<MutexGuard<'_, GpuAllocator<vk::DeviceMemory>>> {
         self.allocator
             .lock()
             .map_err(|_| format_err!("GpuAllocator mutex poisoned"))
@@ -35,12 +209,135 @@ impl Core {
 
     pub fn deallocate(&self, memory: Memory) -> Result<()> {
         unsafe {
-            Ok(self
-                .allocator()?
-                .dealloc(EMD::wrap(&self.device), memory))
+            self.allocator()?.dealloc(EMD::wrap(&self.device), memory);
         }
+        Ok(())
     }
 }
 
+impl Drop for Core {
+    /// Destroy the device and instance. Every object created from them holds a `SharedCore`, so
+    /// they have all been destroyed by now. Panics if validation errors were recorded but never
+    /// reported, unless already panicking.
+    fn drop(&mut self) {
+        unsafe {
+            let _ = self.device.device_wait_idle();
+            if let Ok(mut allocator) = self.allocator.lock() {
+                allocator.cleanup(EMD::wrap(&self.device));
+            }
+            self.device.destroy_device(None);
+            destroy_instance(&self.instance, self.debug_messenger);
+        }
+        let errors = self.debug_messages.take_errors();
+        if !errors.is_empty() && !std::thread::panicking() {
+            panic!("Vulkan validation error: {}", errors.join("\n"));
+        }
+    }
+}
+
+/// Destroy `instance` and its `debug_messenger`, on error paths before a `Core` owns them
+///
+/// # Safety
+/// Every object created from `instance` must already have been destroyed.
+pub unsafe fn destroy_instance(
+    instance: &InstanceLoader,
+    debug_messenger: Option<DebugUtilsMessengerEXT>,
+) {
+    if let Some(messenger) = debug_messenger {
+        instance.destroy_debug_utils_messenger_ext(Some(messenger), None);
+    }
+    instance.destroy_instance(None);
+}
+
+/// Create an instance with the layers and extensions requested in `setup`. If debug utils are
+/// enabled, messages from instance creation and destruction are sent to `debug_messages`.
+pub fn create_instance(
+    entry: &DefaultEntryLoader,
+    app_info: &ApplicationInfo,
+    setup: &VulkanSetup,
+    debug_messages: &Arc<DebugMessages>,
+) -> Result<InstanceLoader> {
+    check_instance_support(entry, setup)?;
+
+    let application_name = CString::new(app_info.name.clone())?;
+    let engine_name = CString::new(crate::ENGINE_NAME)?;
+    let app_info = vk::ApplicationInfoBuilder::new()
+        .application_name(&application_name)
+        .application_version(app_info.version)
+        .engine_name(&engine_name)
+        .engine_version(crate::engine_version())
+        .api_version(setup.api_version);
+
+    let mut messenger_info = messenger_create_info(debug_messages);
+    let mut create_info = vk::InstanceCreateInfoBuilder::new()
+        .application_info(&app_info)
+        .enabled_extension_names(&setup.instance_extensions)
+        .enabled_layer_names(&setup.instance_layers);
+    if debug_utils_enabled(setup) {
+        create_info = create_info.extend_from(&mut messenger_info);
+    }
+
+    Ok(InstanceLoader::new(entry, &create_info, None)?)
+}
+
+/// Check that every instance layer and extension requested in `setup` is available
+pub fn check_instance_support(entry: &DefaultEntryLoader, setup: &VulkanSetup) -> Result<()> {
+    let layers = available_instance_layers(entry)?;
+    for &layer in &setup.instance_layers {
+        let layer = unsafe { CStr::from_ptr(layer) };
+        ensure!(
+            contains_name(&layers, layer),
+            "Instance layer {:?} is not available",
+            layer
+        );
+    }
+
+    let extensions = available_instance_extensions(entry, &setup.instance_layers)?;
+    for &extension in &setup.instance_extensions {
+        let extension = unsafe { CStr::from_ptr(extension) };
+        ensure!(
+            contains_name(&extensions, extension),
+            "Instance extension {:?} is not available",
+            extension
+        );
+    }
+    Ok(())
+}
+
+/// Names of the instance layers installed
+pub fn available_instance_layers(entry: &DefaultEntryLoader) -> Result<Vec<CString>> {
+    Ok(unsafe { entry.enumerate_instance_layer_properties(None) }
+        .result()?
+        .iter()
+        .map(|layer| unsafe { CStr::from_ptr(layer.layer_name.as_ptr()) }.to_owned())
+        .collect())
+}
+
+/// Names of the instance extensions available, including those provided by `layers`
+pub fn available_instance_extensions(
+    entry: &DefaultEntryLoader,
+    layers: &[*const i8],
+) -> Result<Vec<CString>> {
+    let mut names = Vec::new();
+    let layers = layers
+        .iter()
+        .map(|&layer| Some(unsafe { CStr::from_ptr(layer) }));
+    for layer in std::iter::once(None).chain(layers) {
+        let properties =
+            unsafe { entry.enumerate_instance_extension_properties(layer, None) }.result()?;
+        names.extend(
+            properties
+                .iter()
+                .map(|ext| unsafe { CStr::from_ptr(ext.extension_name.as_ptr()) }.to_owned()),
+        );
+    }
+    Ok(names)
+}
+
+/// Whether `name` is in `names`
+pub fn contains_name(names: &[CString], name: &CStr) -> bool {
+    names.iter().any(|n| n.as_c_str() == name)
+}
+
 /// A simple pointer into the core
 pub type SharedCore = Arc<Core>;
@@ -0,0 +1,243 @@
+use crate::*;
+use anyhow::{bail, Result};
+use erupt::{
+    extensions::ext_debug_utils::{self, DebugUtilsMessengerEXT, EXT_DEBUG_UTILS_EXTENSION_NAME},
+    InstanceLoader,
+};
+use std::ffi::{c_void, CStr, CString};
+use std::sync::{Arc, Mutex};
+
+/// Validation errors received by the debug callback, shared with it through its user data
+pub struct DebugMessages {
+    record_errors: bool,
+    errors: Mutex<Vec<String>>,
+}
+
+impl DebugMessages {
+    /// Messages are always logged; validation errors are also recorded if `record_errors` is set,
+    /// to be reported by `Core::check_validation`
+    pub fn new(record_errors: bool) -> Self {
+        Self {
+            record_errors,
+            errors: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Remove and return the validation errors recorded so far
+    pub fn take_errors(&self) -> Vec<String> {
+        match self.errors.lock() {
+            Ok(mut errors) => std::mem::take(&mut *errors),
+            Err(poisoned) => std::mem::take(&mut *poisoned.into_inner()),
+        }
+    }
+
+    fn record(&self, message: String) {
+        if let Ok(mut errors) = self.errors.lock() {
+            errors.push(message);
+        }
+    }
+}
+
+/// Whether `setup` enables `VK_EXT_debug_utils`
+pub fn debug_utils_enabled(setup: &VulkanSetup) -> bool {
+    let debug_utils = unsafe { CStr::from_ptr(EXT_DEBUG_UTILS_EXTENSION_NAME) };
+    setup
+        .instance_extensions
+        .iter()
+        .any(|&ext| unsafe { CStr::from_ptr(ext) } == debug_utils)
+}
+
+/// Create info for a messenger forwarding debug utils messages to the `log` crate and recording
+/// validation errors in `messages`, which must outlive the messenger (and the instance, if this
+/// is chained into the instance create info).
+pub fn messenger_create_info(
+    messages: &Arc<DebugMessages>,
+) -> ext_debug_utils::DebugUtilsMessengerCreateInfoEXTBuilder<'static> {
+    ext_debug_utils::DebugUtilsMessengerCreateInfoEXTBuilder::new()
+        .message_severity(
+            ext_debug_utils::DebugUtilsMessageSeverityFlagsEXT::VERBOSE_EXT
+                | ext_debug_utils::DebugUtilsMessageSeverityFlagsEXT::INFO_EXT
+                | ext_debug_utils::DebugUtilsMessageSeverityFlagsEXT::WARNING_EXT
+                | ext_debug_utils::DebugUtilsMessageSeverityFlagsEXT::ERROR_EXT,
+        )
+        .message_type(
+            ext_debug_utils::DebugUtilsMessageTypeFlagsEXT::GENERAL_EXT
+                | ext_debug_utils::DebugUtilsMessageTypeFlagsEXT::VALIDATION_EXT
+                | ext_debug_utils::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE_EXT,
+        )
+        .pfn_user_callback(Some(debug_callback))
+        .user_data(Arc::as_ptr(messages) as *mut c_void)
+}
+
+/// Create a messenger forwarding debug utils messages to `messages`, if `setup` enables
+/// `VK_EXT_debug_utils`. See `VulkanSetup::panic_on_validation_error`.
+pub fn create_debug_messenger(
+    instance: &InstanceLoader,
+    setup: &VulkanSetup,
+    messages: &Arc<DebugMessages>,
+) -> Result<Option<DebugUtilsMessengerEXT>> {
+    if !debug_utils_enabled(setup) {
+        return Ok(None);
+    }
+
+    let create_info = messenger_create_info(messages);
+    let messenger =
+        unsafe { instance.create_debug_utils_messenger_ext(&create_info, None, None) }.result()?;
+    Ok(Some(messenger))
+}
+
+/// Log a debug utils message at the level matching its severity, and record validation errors in
+/// the `DebugMessages` pointed to by `p_user_data`. This must not panic, as panics cannot unwind
+/// out of the callback.
+unsafe extern "system" fn debug_callback(
+    message_severity: ext_debug_utils::DebugUtilsMessageSeverityFlagBitsEXT,
+    message_types: ext_debug_utils::DebugUtilsMessageTypeFlagsEXT,
+    p_callback_data: *const ext_debug_utils::DebugUtilsMessengerCallbackDataEXT,
+    p_user_data: *mut c_void,
+) -> vk::Bool32 {
+    use ext_debug_utils::DebugUtilsMessageSeverityFlagBitsEXT as Severity;
+
+    let message = match p_callback_data.as_ref() {
+        Some(data) if !data.p_message.is_null() => CStr::from_ptr(data.p_message).to_string_lossy(),
+        _ => "<no message>".into(),
+    };
+
+    let level = match message_severity {
+        Severity::ERROR_EXT => log::Level::Error,
+        Severity::WARNING_EXT => log::Level::Warn,
+        Severity::INFO_EXT => log::Level::Debug,
+        _ => log::Level::Trace,
+    };
+    log::log!(target: "vulkan", level, "[{:?}] {}", message_types, message);
+
+    if message_severity == Severity::ERROR_EXT
+        && message_types.contains(ext_debug_utils::DebugUtilsMessageTypeFlagsEXT::VALIDATION_EXT)
+    {
+        if let Some(messages) = (p_user_data as *const DebugMessages).as_ref() {
+            if messages.record_errors {
+                messages.record(message.into_owned());
+            }
+        }
+    }
+
+    vk::FALSE
+}
+
+/// Vulkan handles which may be given a debug name; see `Core::set_name`
+pub trait DebugHandle: Copy {
+    fn object_type(&self) -> vk::ObjectType;
+    fn raw_handle(&self) -> u64;
+}
+
+macro_rules! debug_handles {
+    ($($handle:ty),* $(,)?) => {
+        $(impl DebugHandle for $handle {
+            fn object_type(&self) -> vk::ObjectType {
+                <$handle>::TYPE
+            }
+
+            fn raw_handle(&self) -> u64 {
+                self.0 as u64
+            }
+        })*
+    };
+}
+
+debug_handles!(
+    vk::Buffer,
+    vk::BufferView,
+    vk::CommandBuffer,
+    vk::CommandPool,
+    vk::DescriptorPool,
+    vk::DescriptorSet,
+    vk::DescriptorSetLayout,
+    vk::DeviceMemory,
+    vk::Fence,
+    vk::Framebuffer,
+    vk::Image,
+    vk::ImageView,
+    vk::Pipeline,
+    vk::PipelineLayout,
+    vk::Queue,
+    vk::RenderPass,
+    vk::Sampler,
+    vk::Semaphore,
+    vk::ShaderModule,
+);
+
+impl Core {
+    /// Report the validation errors recorded since the last check, if
+    /// `VulkanSetup::panic_on_validation_error` is set. Called after each queue submission.
+    pub fn check_validation(&self) -> Result<()> {
+        let errors = self.debug_messages.take_errors();
+        if !errors.is_empty() {
+            bail!("Vulkan validation error: {}", errors.join("\n"));
+        }
+        Ok(())
+    }
+
+    /// Whether debug utils are enabled, and therefore names and labels are recorded
+    pub fn debug_utils_enabled(&self) -> bool {
+        self.device.set_debug_utils_object_name_ext.is_some()
+    }
+
+    /// Give `handle` a name visible in validation messages and graphics debuggers. Does nothing if
+    /// debug utils are not enabled.
+    pub fn set_name(&self, handle: impl DebugHandle, name: &str) -> Result<()> {
+        if !self.debug_utils_enabled() {
+            return Ok(());
+        }
+        let name = CString::new(name)?;
+        let name_info = ext_debug_utils::DebugUtilsObjectNameInfoEXTBuilder::new()
+            .object_type(handle.object_type())
+            .object_handle(handle.raw_handle())
+            .object_name(&name);
+        unsafe { self.device.set_debug_utils_object_name_ext(&name_info) }.result()?;
+        Ok(())
+    }
+
+    /// Open a labelled region of `command_buffer`, which is closed when the returned scope is
+    /// dropped. Does nothing if debug utils are not enabled.
+    pub fn label<'a>(&'a self, command_buffer: vk::CommandBuffer, name: &str) -> LabelScope<'a> {
+        LabelScope::new(self, command_buffer, name)
+    }
+}
+
+/// A labelled region of a command buffer, see `Core::label`
+pub struct LabelScope<'a> {
+    core: &'a Core,
+    command_buffer: vk::CommandBuffer,
+    active: bool,
+}
+
+impl<'a> LabelScope<'a> {
+    fn new(core: &'a Core, command_buffer: vk::CommandBuffer, name: &str) -> Self {
+        // Labels are informational only, so names containing nul bytes are truncated
+        let name = CString::new(name.split('\0').next().unwrap_or_default()).unwrap_or_default();
+        let active = core.debug_utils_enabled();
+        if active {
+            let label = ext_debug_utils::DebugUtilsLabelEXTBuilder::new().label_name(&name);
+            unsafe {
+                core.device
+                    .cmd_begin_debug_utils_label_ext(command_buffer, &label)
+            };
+        }
+        Self {
+            core,
+            command_buffer,
+            active,
+        }
+    }
+}
+
+impl Drop for LabelScope<'_> {
+    fn drop(&mut self) {
+        if self.active {
+            unsafe {
+                self.core
+                    .device
+                    .cmd_end_debug_utils_label_ext(self.command_buffer)
+            };
+        }
+    }
+}
@@ -1,47 +1,1235 @@
 use crate::core::SharedCore;
+use crate::mem_objects::MemObject;
+use crate::pacing::{FramePacer, FrameStats};
+use crate::scene::{Scene, SceneInstance};
+use crate::transfer::Submission;
+use crate::windowed::{
+    hardware::{select_present_mode, SurfaceInfo},
+    swapchain,
+};
 use crate::*;
-use slotmap::{DefaultKey, SlotMap};
-use erupt::cstr;
+use anyhow::{ensure, format_err, Result};
+use erupt::extensions::{
+    khr_surface::{PresentModeKHR, SurfaceKHR},
+    khr_swapchain,
+};
+use gpu_alloc_erupt::EruptMemoryDevice as EMD;
+use slotmap::{DefaultKey, Key, SlotMap};
+use std::any::Any;
+use std::convert::TryInto;
+use std::path::Path;
 
 /// Number of frames in-flight. >1 means the GPU and CPU work in parallel
 const N_FRAMES: usize = 2;
 
+/// Format used for all depth attachments
+pub const DEPTH_FORMAT: vk::Format = vk::Format::D32_SFLOAT;
+
+/// Push constants available to the vertex stage of every `Material`. Matrices are column-major.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct DrawPushConstants {
+    pub model: [f32; 16],
+}
+
+unsafe impl bytemuck::Zeroable for DrawPushConstants {}
+unsafe impl bytemuck::Pod for DrawPushConstants {}
+
+/// Contents of the uniform buffer bound at set 0, binding 0 for every `Material`. Matches the
+/// std140 block `uniform Camera { mat4 view; mat4 projection; float time; }`. Matrices are
+/// column-major.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct CameraUniforms {
+    pub view: [f32; 16],
+    pub projection: [f32; 16],
+    pub time: f32,
+    _padding: [f32; 3],
+}
+
+unsafe impl bytemuck::Zeroable for CameraUniforms {}
+unsafe impl bytemuck::Pod for CameraUniforms {}
+
+impl CameraUniforms {
+    pub fn new(view: [f32; 16], projection: [f32; 16], time: f32) -> Self {
+        Self {
+            view,
+            projection,
+            time,
+            _padding: [0.; 3],
+        }
+    }
+}
+
 pub struct Engine {
+    pub swapchain: khr_swapchain::SwapchainKHR,
     pub swapchain_images: Vec<SwapchainImage>,
     pub depth_image: Image,
     pub command_buffers: [vk::CommandBuffer; N_FRAMES],
     pub descriptor_set_layout: vk::DescriptorSetLayout,
+    /// Layout of set 1, which binds a material's texture
+    pub texture_set_layout: vk::DescriptorSetLayout,
     pub descriptor_pool: vk::DescriptorPool,
+    /// Set 0 for each frame, containing that frame's camera buffer
+    pub descriptor_sets: [vk::DescriptorSet; N_FRAMES],
+    /// Host-visible `CameraUniforms` for each frame
+    pub camera_buffers: [MemObject<vk::Buffer>; N_FRAMES],
     pub command_pool: vk::CommandPool,
     pub render_pass: vk::RenderPass,
     pub materials: SlotMap<DefaultKey, Material>,
-    pub meshes: SlotMap<DefaultKey, MeshBundle>,
+    pub meshes: SlotMap<MeshKey, MeshBundle>,
+    pub textures: SlotMap<TextureKey, Texture>,
+    /// Meshes in `meshes` whose uploads have not yet been acquired by the graphics queue
+    streaming: Vec<(MeshKey, Submission)>,
     pub frame_sync: [FrameSync; N_FRAMES],
     /// The index of the frame that is currently writeable
     /// (or equivalently: not in-use by the GPU).
     pub frame_idx: usize,
+    /// The swapchain image acquired for the frame currently being recorded
+    pub image_idx: Option<u32>,
+    pub clear_color: [f32; 4],
+    pub surface: SurfaceKHR,
+    pub surface_info: SurfaceInfo,
+    pub hardware: HardwareSelection,
+    /// Size of the window in pixels, used when the surface does not dictate an extent
+    pub window_size: vk::Extent2D,
+    /// Set when the swapchain no longer matches the surface, and must be rebuilt before the next
+    /// frame is acquired
+    pub swapchain_outdated: bool,
+    /// Frame limiter and frame time statistics
+    pub pacer: FramePacer,
     pub _core: SharedCore,
 }
 
-pub fn vk_setup(validation: bool) -> VulkanSetup {
-    const LAYER_KHRONOS_VALIDATION: *const i8 = cstr!("VK_LAYER_KHRONOS_validation");
-    use erupt::extensions::ext_debug_utils::EXT_DEBUG_UTILS_EXTENSION_NAME;
-    let api_version = vk::make_version(1, 0, 0);
-    if validation {
-        VulkanSetup {
-            instance_layers: vec![],
-            instance_extensions: vec![],
-            device_layers: vec![],
-            device_extensions: vec![],
-            api_version,
-        }
-    } else {
-        VulkanSetup {
-            instance_layers: vec![LAYER_KHRONOS_VALIDATION],
-            instance_extensions: vec![EXT_DEBUG_UTILS_EXTENSION_NAME],
-            device_layers: vec![LAYER_KHRONOS_VALIDATION],
-            device_extensions: vec![],
-            api_version,
+impl Engine {
+    /// Create an engine presenting to `surface`. `window_size` is used for the swapchain extent
+    /// only if the surface does not dictate one.
+    pub fn new(
+        core: SharedCore,
+        hardware: HardwareSelection,
+        surface: SurfaceKHR,
+        surface_info: SurfaceInfo,
+        window_size: vk::Extent2D,
+    ) -> Result<Self> {
+        let render_pass = create_render_pass(
+            &core,
+            surface_info.format.format,
+            vk::ImageLayout::PRESENT_SRC_KHR,
+        )?;
+        core.set_name(render_pass, "Main pass")?;
+
+        let surface_caps = swapchain::surface_caps(&core, &hardware, surface)?;
+        let extent = swapchain::select_extent(&surface_caps, window_size);
+        let swapchain = swapchain::create_swapchain(
+            &core,
+            surface,
+            &surface_info,
+            &surface_caps,
+            extent,
+            None,
+        )?;
+        let depth_image = create_depth_image(&core, extent)?;
+        let swapchain_images = swapchain::create_swapchain_images(
+            &core,
+            swapchain,
+            surface_info.format.format,
+            render_pass,
+            depth_image.view,
+            extent,
+        )?;
+
+        // Command buffers
+        let create_info = vk::CommandPoolCreateInfoBuilder::new()
+            .queue_family_index(hardware.graphics_queue_family)
+            .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER);
+        let command_pool =
+            unsafe { core.device.create_command_pool(&create_info, None, None) }.result()?;
+
+        let allocate_info = vk::CommandBufferAllocateInfoBuilder::new()
+            .command_pool(command_pool)
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_buffer_count(N_FRAMES as u32);
+        let command_buffers: [vk::CommandBuffer; N_FRAMES] =
+            unsafe { core.device.allocate_command_buffers(&allocate_info) }
+                .result()?
+                .try_into()
+                .map_err(|_| format_err!("Wrong number of command buffers allocated"))?;
+        for (i, &command_buffer) in command_buffers.iter().enumerate() {
+            core.set_name(command_buffer, &format!("Frame {} commands", i))?;
+        }
+
+        // Descriptors
+        let bindings = [vk::DescriptorSetLayoutBindingBuilder::new()
+            .binding(0)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)];
+        let create_info = vk::DescriptorSetLayoutCreateInfoBuilder::new().bindings(&bindings);
+        let descriptor_set_layout = unsafe {
+            core.device
+                .create_descriptor_set_layout(&create_info, None, None)
+        }
+        .result()?;
+        let texture_set_layout = crate::texture::create_texture_set_layout(&core)?;
+
+        let pool_sizes = [vk::DescriptorPoolSizeBuilder::new()
+            ._type(vk::DescriptorType::UNIFORM_BUFFER)
+            .descriptor_count(N_FRAMES as u32)];
+        let create_info = vk::DescriptorPoolCreateInfoBuilder::new()
+            .pool_sizes(&pool_sizes)
+            .max_sets(N_FRAMES as u32);
+        let descriptor_pool =
+            unsafe { core.device.create_descriptor_pool(&create_info, None, None) }.result()?;
+
+        let set_layouts = [descriptor_set_layout; N_FRAMES];
+        let allocate_info = vk::DescriptorSetAllocateInfoBuilder::new()
+            .descriptor_pool(descriptor_pool)
+            .set_layouts(&set_layouts);
+        let descriptor_sets: [vk::DescriptorSet; N_FRAMES] =
+            unsafe { core.device.allocate_descriptor_sets(&allocate_info) }
+                .result()?
+                .try_into()
+                .map_err(|_| format_err!("Wrong number of descriptor sets allocated"))?;
+
+        // Camera uniform buffers
+        let mut camera_buffers = Vec::with_capacity(N_FRAMES);
+        for &descriptor_set in &descriptor_sets {
+            let create_info = vk::BufferCreateInfoBuilder::new()
+                .size(std::mem::size_of::<CameraUniforms>() as u64)
+                .usage(vk::BufferUsageFlags::UNIFORM_BUFFER)
+                .sharing_mode(vk::SharingMode::EXCLUSIVE);
+            let buffer = MemObject::<vk::Buffer>::new(
+                &core,
+                create_info,
+                gpu_alloc::UsageFlags::UPLOAD | gpu_alloc::UsageFlags::HOST_ACCESS,
+            )?;
+
+            let buffer_infos = [vk::DescriptorBufferInfoBuilder::new()
+                .buffer(buffer.instance)
+                .offset(0)
+                .range(std::mem::size_of::<CameraUniforms>() as u64)];
+            let writes = [vk::WriteDescriptorSetBuilder::new()
+                .dst_set(descriptor_set)
+                .dst_binding(0)
+                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                .buffer_info(&buffer_infos)];
+            unsafe { core.device.update_descriptor_sets(&writes, &[]) };
+
+            let name = format!("Frame {} camera", camera_buffers.len());
+            core.set_name(buffer.instance, &name)?;
+            core.set_name(descriptor_set, &name)?;
+            camera_buffers.push(buffer);
+        }
+        let camera_buffers = camera_buffers
+            .try_into()
+            .map_err(|_| format_err!("Wrong number of camera buffers created"))?;
+
+        // Synchronization
+        let frame_sync = (0..N_FRAMES)
+            .map(|i| {
+                let frame_sync = FrameSync::new(&core)?;
+                frame_sync.set_name(&format!("Frame {}", i))?;
+                Ok(frame_sync)
+            })
+            .collect::<Result<Vec<_>>>()?
+            .try_into()
+            .map_err(|_| format_err!("Wrong number of frame sync objects created"))?;
+
+        Ok(Self {
+            swapchain,
+            swapchain_images,
+            depth_image,
+            command_buffers,
+            descriptor_set_layout,
+            texture_set_layout,
+            descriptor_pool,
+            descriptor_sets,
+            camera_buffers,
+            command_pool,
+            render_pass,
+            materials: SlotMap::new(),
+            meshes: SlotMap::with_key(),
+            textures: SlotMap::with_key(),
+            streaming: Vec::new(),
+            frame_sync,
+            frame_idx: 0,
+            image_idx: None,
+            clear_color: [0., 0., 0., 1.],
+            surface,
+            surface_info,
+            hardware,
+            window_size,
+            swapchain_outdated: false,
+            pacer: FramePacer::default(),
+            _core: core,
+        })
+    }
+
+    /// Notify the engine that the window was resized. The swapchain is rebuilt at the start of
+    /// the next frame.
+    pub fn resize(&mut self, window_size: vk::Extent2D) {
+        self.window_size = window_size;
+        self.swapchain_outdated = true;
+    }
+
+    /// Present with the first of `present_modes` the surface supports, falling back to FIFO. The
+    /// swapchain is rebuilt at the start of the next frame if the present mode changes.
+    pub fn set_present_modes(&mut self, present_modes: &[PresentModeKHR]) -> Result<()> {
+        let present_mode = select_present_mode(
+            &self._core.instance,
+            self.hardware.physical_device,
+            self.surface,
+            present_modes,
+        )?;
+        if present_mode != self.surface_info.present_mode {
+            self.surface_info.present_mode = present_mode;
+            self.swapchain_outdated = true;
+        }
+        Ok(())
+    }
+
+    /// Switch between vsync (FIFO) and presenting as soon as possible (IMMEDIATE, or MAILBOX if
+    /// unavailable). The swapchain is rebuilt at the start of the next frame if needed.
+    pub fn set_vsync(&mut self, vsync: bool) -> Result<()> {
+        if vsync {
+            self.set_present_modes(&[PresentModeKHR::FIFO_KHR])
+        } else {
+            self.set_present_modes(&[PresentModeKHR::IMMEDIATE_KHR, PresentModeKHR::MAILBOX_KHR])
+        }
+    }
+
+    /// Whether presentation waits for vertical blanking
+    pub fn vsync(&self) -> bool {
+        matches!(
+            self.surface_info.present_mode,
+            PresentModeKHR::FIFO_KHR | PresentModeKHR::FIFO_RELAXED_KHR
+        )
+    }
+
+    /// Limit the frame rate to `fps` by sleeping in `begin_frame`, or remove the limit with `None`
+    pub fn set_target_fps(&mut self, fps: Option<f32>) {
+        self.pacer.set_target_fps(fps);
+    }
+
+    /// Frame time statistics over recent frames
+    pub fn frame_stats(&self) -> FrameStats {
+        self.pacer.stats()
+    }
+
+    /// Re-create the swapchain, along with the depth image and framebuffers sized to it. Waits for
+    /// the device to become idle first. Returns `false` if the surface currently has no area (for
+    /// example when the window is minimized), in which case nothing is rebuilt.
+    pub fn rebuild_swapchain(&mut self) -> Result<bool> {
+        let core = self._core.clone();
+        let surface_caps = swapchain::surface_caps(&core, &self.hardware, self.surface)?;
+        let extent = swapchain::select_extent(&surface_caps, self.window_size);
+        if extent.width == 0 || extent.height == 0 {
+            return Ok(false);
+        }
+
+        unsafe {
+            core.device.device_wait_idle().result()?;
+        }
+
+        let swapchain = swapchain::create_swapchain(
+            &core,
+            self.surface,
+            &self.surface_info,
+            &surface_caps,
+            extent,
+            Some(self.swapchain),
+        )?;
+
+        self.swapchain_images.clear();
+        unsafe {
+            core.device
+                .destroy_swapchain_khr(Some(self.swapchain), None);
+        }
+        self.swapchain = swapchain;
+
+        self.depth_image = create_depth_image(&core, extent)?;
+        self.swapchain_images = swapchain::create_swapchain_images(
+            &core,
+            swapchain,
+            self.surface_info.format.format,
+            self.render_pass,
+            self.depth_image.view,
+            extent,
+        )?;
+
+        self.swapchain_outdated = false;
+        Ok(true)
+    }
+
+    /// Wait for the current frame to become available and acquire a swapchain image, rebuilding
+    /// the swapchain first if it is out of date. The returned command buffer is inside the render
+    /// pass, with viewport and scissor set. Returns `None` if there is nothing to present to this
+    /// frame (for example while the window is minimized); `end_frame` must not be called then.
+    pub fn begin_frame(&mut self) -> Result<Option<vk::CommandBuffer>> {
+        self.begin_frame_inner(None)
+    }
+
+    /// Like `begin_frame`, but first records `pre_pass` into the command buffer outside of the
+    /// render pass, followed by a barrier making compute shader writes visible to the pass. Use
+    /// this to dispatch `ComputeKernel`s whose results are drawn in the same frame.
+    pub fn begin_frame_with(
+        &mut self,
+        pre_pass: impl FnOnce(vk::CommandBuffer),
+    ) -> Result<Option<vk::CommandBuffer>> {
+        self.begin_frame_inner(Some(Box::new(pre_pass)))
+    }
+
+    fn begin_frame_inner(
+        &mut self,
+        pre_pass: Option<Box<dyn FnOnce(vk::CommandBuffer) + '_>>,
+    ) -> Result<Option<vk::CommandBuffer>> {
+        self.pacer.begin_frame();
+        if self.swapchain_outdated && !self.rebuild_swapchain()? {
+            return Ok(None);
+        }
+
+        let command_buffer = self.command_buffers[self.frame_idx];
+        let device = &self._core.device;
+
+        unsafe {
+            device
+                .wait_for_fences(&[self.frame_sync[self.frame_idx].fence], true, u64::MAX)
+                .result()?;
+        }
+        // Nothing dropped during this frame's last use can still be in use by the GPU
+        self.frame_sync[self.frame_idx].garbage.clear();
+
+        let sync = &self.frame_sync[self.frame_idx];
+        let acquired = unsafe {
+            device.acquire_next_image_khr(
+                self.swapchain,
+                u64::MAX,
+                Some(sync.image_available),
+                None,
+                None,
+            )
+        };
+        let image_idx = match acquired.raw {
+            vk::Result::ERROR_OUT_OF_DATE_KHR => {
+                self.swapchain_outdated = true;
+                return Ok(None);
+            }
+            vk::Result::SUBOPTIMAL_KHR => {
+                self.swapchain_outdated = true;
+                acquired.result()?
+            }
+            _ => acquired.result()?,
+        };
+        self.image_idx = Some(image_idx);
+
+        // The image may still be in use by a previous frame which acquired it
+        let image = &mut self.swapchain_images[image_idx as usize];
+        if !image.fence.is_null() {
+            unsafe {
+                device
+                    .wait_for_fences(&[image.fence], true, u64::MAX)
+                    .result()?;
+            }
+        }
+        image.fence = sync.fence;
+
+        unsafe {
+            device.reset_command_buffer(command_buffer, None).result()?;
+            let begin_info = vk::CommandBufferBeginInfoBuilder::new()
+                .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+            device
+                .begin_command_buffer(command_buffer, &begin_info)
+                .result()?;
+        }
+
+        // Take ownership of streamed meshes whose uploads have completed. An image has been
+        // acquired, so failures are logged rather than returned.
+        let mut i = 0;
+        while i < self.streaming.len() {
+            match self.streaming[i].1.is_complete() {
+                Ok(true) => {
+                    let (_, upload) = self.streaming.swap_remove(i);
+                    upload.record_acquire(command_buffer);
+                }
+                Ok(false) => i += 1,
+                Err(e) => {
+                    // The mesh was never drawn, and its contents are undefined
+                    let (key, upload) = self.streaming.swap_remove(i);
+                    log::error!("Upload of mesh {:?} failed: {}", key, e);
+                    drop(upload);
+                    self.meshes.remove(key);
+                }
+            }
+        }
+
+        if let Some(pre_pass) = pre_pass {
+            pre_pass(command_buffer);
+            crate::compute::compute_barrier(
+                &self._core,
+                command_buffer,
+                vk::PipelineStageFlags::DRAW_INDIRECT
+                    | vk::PipelineStageFlags::VERTEX_INPUT
+                    | vk::PipelineStageFlags::VERTEX_SHADER
+                    | vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::AccessFlags::INDIRECT_COMMAND_READ
+                    | vk::AccessFlags::INDEX_READ
+                    | vk::AccessFlags::VERTEX_ATTRIBUTE_READ
+                    | vk::AccessFlags::UNIFORM_READ
+                    | vk::AccessFlags::SHADER_READ,
+            );
+        }
+
+        let image = &self.swapchain_images[image_idx as usize];
+        begin_render_pass(
+            &self._core,
+            command_buffer,
+            self.render_pass,
+            image.framebuffer,
+            image.extent,
+            self.clear_color,
+        );
+
+        Ok(Some(command_buffer))
+    }
+
+    /// Record and present a single frame using `record`. Does nothing if there is nothing to
+    /// present to; see `begin_frame`.
+    pub fn frame(&mut self, record: impl FnOnce(vk::CommandBuffer)) -> Result<()> {
+        if let Some(command_buffer) = self.begin_frame()? {
+            record(command_buffer);
+            self.end_frame()?;
+        }
+        Ok(())
+    }
+
+    /// Upload a mesh and add it to `meshes`
+    pub fn add_mesh<V: bytemuck::Pod>(
+        &mut self,
+        vertices: &[V],
+        indices: &[u32],
+    ) -> Result<MeshKey> {
+        let mesh = MeshBundle::new(&self._core, vertices, indices)?;
+        self.meshes.try_insert_with_key(|key| {
+            mesh.set_name(&format!("Mesh {:?}", key.data()))?;
+            Ok(mesh)
+        })
+    }
+
+    /// Add a mesh to `meshes` without waiting for its upload to complete. Draws using the mesh
+    /// are skipped until it is ready; see `mesh_ready`.
+    pub fn stream_mesh<V: bytemuck::Pod>(
+        &mut self,
+        vertices: &[V],
+        indices: &[u32],
+    ) -> Result<MeshKey> {
+        let (mesh, upload) = MeshBundle::new_streamed(&self._core, vertices, indices)?;
+        let streaming = &mut self.streaming;
+        self.meshes.try_insert_with_key(|key| {
+            if let Err(e) = mesh.set_name(&format!("Mesh {:?}", key.data())) {
+                // Wait for the upload before its destination buffers are destroyed
+                drop(upload);
+                return Err(e);
+            }
+            streaming.push((key, upload));
+            Ok(mesh)
+        })
+    }
+
+    /// Load each object and group in an OBJ file as a mesh in `meshes`, named after the object.
+    /// Vertices use the `Vertex` layout.
+    pub fn load_obj(&mut self, path: impl AsRef<Path>) -> Result<Vec<MeshKey>> {
+        let objects = crate::obj::load_obj(path)?;
+        let mut keys = Vec::with_capacity(objects.len());
+        let result = (|| {
+            for obj in &objects {
+                let key = self.add_mesh(&obj.vertices, &obj.indices)?;
+                keys.push(key);
+                self.set_mesh_label(key, &obj.name)?;
+            }
+            Ok(())
+        })();
+        if let Err(e) = result {
+            // Remove the meshes added before the failure
+            for key in keys {
+                self.remove_mesh(key);
+            }
+            return Err(e);
+        }
+        Ok(keys)
+    }
+
+    /// Upload every mesh primitive and texture in `scene`, naming them after their scene
+    /// counterparts where named. Material parameters are left to the caller; see `PbrParams`.
+    /// Draw the result with `Scene::draws`. On failure, nothing is left uploaded.
+    pub fn add_scene(&mut self, scene: &Scene) -> Result<SceneInstance> {
+        let mut instance = SceneInstance {
+            meshes: Vec::with_capacity(scene.meshes.len()),
+            textures: Vec::with_capacity(scene.textures.len()),
+        };
+        let result = (|| {
+            for texture in &scene.textures {
+                let key = self.add_texture(
+                    texture.extent.width,
+                    texture.extent.height,
+                    crate::image_file::rgba8_format(texture.srgb),
+                    &texture.pixels,
+                )?;
+                instance.textures.push(key);
+                if let Some(name) = &texture.name {
+                    self.set_texture_label(key, name)?;
+                }
+            }
+
+            for mesh in &scene.meshes {
+                instance
+                    .meshes
+                    .push(Vec::with_capacity(mesh.primitives.len()));
+                for (i, primitive) in mesh.primitives.iter().enumerate() {
+                    let key = self.add_mesh(&primitive.vertices, &primitive.indices)?;
+                    if let Some(primitives) = instance.meshes.last_mut() {
+                        primitives.push((key, primitive.material));
+                    }
+                    if let Some(name) = &mesh.name {
+                        self.set_mesh_label(key, &format!("{} {}", name, i))?;
+                    }
+                }
+            }
+            Ok(())
+        })();
+        if let Err(e) = result {
+            self.remove_scene(instance);
+            return Err(e);
+        }
+        Ok(instance)
+    }
+
+    /// Remove the meshes and textures uploaded by `add_scene`
+    pub fn remove_scene(&mut self, instance: SceneInstance) {
+        for (key, _) in instance.meshes.into_iter().flatten() {
+            self.remove_mesh(key);
+        }
+        for key in instance.textures {
+            self.remove_texture(key);
+        }
+    }
+
+    /// Import a glTF 2.0 file and upload it; see `Scene::load` and `add_scene`
+    pub fn load_gltf(&mut self, path: impl AsRef<Path>) -> Result<(Scene, SceneInstance)> {
+        let scene = Scene::load(path)?;
+        let instance = self.add_scene(&scene)?;
+        Ok((scene, instance))
+    }
+
+    /// Whether a mesh exists and may be drawn
+    pub fn mesh_ready(&self, key: MeshKey) -> bool {
+        self.meshes.contains_key(key) && !self.streaming.iter().any(|(k, _)| *k == key)
+    }
+
+    /// Name a mesh's buffers after `label` for debugging, in place of its key
+    pub fn set_mesh_label(&self, key: MeshKey, label: &str) -> Result<()> {
+        self.meshes
+            .get(key)
+            .ok_or_else(|| format_err!("Mesh {:?} does not exist", key))?
+            .set_name(label)
+    }
+
+    /// Remove a mesh from `meshes`. It is freed once no frame in flight can be using it; see
+    /// `defer_drop`. Does nothing if the mesh has already been removed.
+    pub fn remove_mesh(&mut self, key: MeshKey) {
+        if let Some(mesh) = self.meshes.remove(key) {
+            // Dropping a pending upload waits for it to complete
+            self.streaming.retain(|(k, _)| *k != key);
+            self.defer_drop(mesh);
+        }
+    }
+
+    /// Build a material from SPIR-V vertex and fragment shaders, and add it to `materials`
+    pub fn add_material(
+        &mut self,
+        vertex_spv: &[u8],
+        fragment_spv: &[u8],
+        desc: MaterialDesc,
+    ) -> Result<DefaultKey> {
+        let material = Material::new(
+            &self._core,
+            self.render_pass,
+            &[self.descriptor_set_layout, self.texture_set_layout],
+            vertex_spv,
+            fragment_spv,
+            &desc,
+        )?;
+        self.materials.try_insert_with_key(|key| {
+            material.set_name(&format!("Material {:?}", key.data()))?;
+            Ok(material)
+        })
+    }
+
+    /// Name a material's pipeline after `label` for debugging, in place of its key
+    pub fn set_material_label(&self, key: DefaultKey, label: &str) -> Result<()> {
+        self.materials
+            .get(key)
+            .ok_or_else(|| format_err!("Material {:?} does not exist", key))?
+            .set_name(label)
+    }
+
+    /// Remove a material from `materials`; see `remove_mesh`
+    pub fn remove_material(&mut self, key: DefaultKey) {
+        if let Some(material) = self.materials.remove(key) {
+            self.defer_drop(material);
+        }
+    }
+
+    /// Bind `texture` at set 1 when drawing with a material, or bind nothing if `None`
+    pub fn set_material_texture(
+        &mut self,
+        material: DefaultKey,
+        texture: Option<TextureKey>,
+    ) -> Result<()> {
+        if let Some(texture) = texture {
+            ensure!(
+                self.textures.contains_key(texture),
+                "Texture {:?} does not exist",
+                texture
+            );
+        }
+        self.materials
+            .get_mut(material)
+            .ok_or_else(|| format_err!("Material {:?} does not exist", material))?
+            .texture = texture;
+        Ok(())
+    }
+
+    /// Upload a texture with a full mip chain and add it to `textures`. `data` holds tightly
+    /// packed rows of `format` texels, top to bottom.
+    pub fn add_texture(
+        &mut self,
+        width: u32,
+        height: u32,
+        format: vk::Format,
+        data: &[u8],
+    ) -> Result<TextureKey> {
+        self.add_texture_with_mipmaps(width, height, format, data, true)
+    }
+
+    /// Like `add_texture`, generating mipmaps only if `mipmaps` is set
+    pub fn add_texture_with_mipmaps(
+        &mut self,
+        width: u32,
+        height: u32,
+        format: vk::Format,
+        data: &[u8],
+        mipmaps: bool,
+    ) -> Result<TextureKey> {
+        let texture = Texture::new(
+            &self._core,
+            width,
+            height,
+            format,
+            data,
+            mipmaps,
+            self.texture_set_layout,
+        )?;
+        self.textures.try_insert_with_key(|key| {
+            texture.set_name(&format!("Texture {:?}", key.data()))?;
+            Ok(texture)
+        })
+    }
+
+    /// Load a PNG or JPEG file and add it to `textures`, named after its path. `srgb` selects
+    /// whether the texels are sampled as sRGB or linear.
+    pub fn load_texture(&mut self, path: impl AsRef<Path>, srgb: bool) -> Result<TextureKey> {
+        let texture = Texture::from_file(&self._core, path, srgb, self.texture_set_layout)?;
+        Ok(self.textures.insert(texture))
+    }
+
+    /// Like `load_texture`, decoding a PNG or JPEG file held in memory
+    pub fn load_texture_from_memory(&mut self, bytes: &[u8], srgb: bool) -> Result<TextureKey> {
+        let texture = Texture::from_memory(&self._core, bytes, srgb, self.texture_set_layout)?;
+        self.textures.try_insert_with_key(|key| {
+            texture.set_name(&format!("Texture {:?}", key.data()))?;
+            Ok(texture)
+        })
+    }
+
+    /// Name a texture's objects after `label` for debugging, in place of its key
+    pub fn set_texture_label(&self, key: TextureKey, label: &str) -> Result<()> {
+        self.textures
+            .get(key)
+            .ok_or_else(|| format_err!("Texture {:?} does not exist", key))?
+            .set_name(label)
+    }
+
+    /// Remove a texture from `textures`; see `remove_mesh`. Materials still referring to it no
+    /// longer bind a texture at set 1.
+    pub fn remove_texture(&mut self, key: TextureKey) {
+        if let Some(texture) = self.textures.remove(key) {
+            self.defer_drop(texture);
+        }
+    }
+
+    /// Drop `resource` once every frame which may be using it has finished on the GPU. May be
+    /// called at any time, including between `begin_frame` and `end_frame`.
+    pub fn defer_drop(&mut self, resource: impl Any) {
+        // Mid-frame, the frame being recorded is the last which may use the resource; otherwise
+        // it is the frame most recently submitted
+        let frame_idx = if self.image_idx.is_some() {
+            self.frame_idx
+        } else {
+            (self.frame_idx + N_FRAMES - 1) % N_FRAMES
+        };
+        self.frame_sync[frame_idx].garbage.push(Box::new(resource));
+    }
+
+    /// Write the camera uniforms for the frame currently being recorded. Must be called between
+    /// `begin_frame` and `end_frame`.
+    pub fn update_camera(&mut self, uniforms: &CameraUniforms) -> Result<()> {
+        unsafe {
+            self.camera_buffers[self.frame_idx]
+                .memory_mut()
+                .write_bytes(
+                    EMD::wrap(&self._core.device),
+                    0,
+                    bytemuck::bytes_of(uniforms),
+                )?;
+        }
+        Ok(())
+    }
+
+    /// Record and present a frame containing everything in `packet`
+    pub fn draw(&mut self, packet: &FramePacket) -> Result<()> {
+        if let Some(command_buffer) = self.begin_frame()? {
+            // Present the frame even if recording failed, so the acquired image is released
+            let uniforms = CameraUniforms::new(packet.view, packet.projection, packet.time);
+            let recorded = self
+                .update_camera(&uniforms)
+                .and_then(|_| self.record_draws(command_buffer, packet));
+            self.end_frame()?;
+            recorded?;
+        }
+        Ok(())
+    }
+
+    /// Record the draws in `packet` into a command buffer returned from `begin_frame`, using this
+    /// frame's camera uniforms. The camera matrices in `packet` are not used; see `update_camera`.
+    /// Draws of meshes which are still streaming are skipped.
+    pub fn record_draws(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        packet: &FramePacket,
+    ) -> Result<()> {
+        let filtered;
+        let packet = if self.streaming.is_empty() {
+            packet
+        } else {
+            let mut ready = packet.clone();
+            ready
+                .draws
+                .retain(|draw| !self.streaming.iter().any(|(k, _)| *k == draw.mesh));
+            filtered = ready;
+            &filtered
+        };
+        record_draws(
+            &self._core,
+            command_buffer,
+            self.descriptor_sets[self.frame_idx],
+            &self.materials,
+            &self.meshes,
+            &self.textures,
+            packet,
+        )
+    }
+
+    /// Finish the frame begun with `begin_frame`, then submit and present it
+    pub fn end_frame(&mut self) -> Result<()> {
+        let image_idx = self
+            .image_idx
+            .take()
+            .ok_or_else(|| format_err!("end_frame() called without begin_frame()"))?;
+        let sync = &self.frame_sync[self.frame_idx];
+        let command_buffer = self.command_buffers[self.frame_idx];
+        let device = &self._core.device;
+
+        let queue = self._core.graphics_queue;
+        let signal_semaphores = [sync.semaphore];
+        let submit = |command_buffers: &[vk::CommandBuffer]| -> Result<()> {
+            let wait_semaphores = [sync.image_available];
+            let wait_stages = [vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
+            let submit_info = vk::SubmitInfoBuilder::new()
+                .wait_semaphores(&wait_semaphores)
+                .wait_dst_stage_mask(&wait_stages)
+                .command_buffers(command_buffers)
+                .signal_semaphores(&signal_semaphores);
+            unsafe {
+                device.reset_fences(&[sync.fence]).result()?;
+                device
+                    .queue_submit(queue, &[submit_info], Some(sync.fence))
+                    .result()?;
+            }
+            Ok(())
+        };
+
+        // If the frame's commands cannot be submitted, submit nothing in their place, so that
+        // the fence is still signalled and the image is still presented
+        let submitted = unsafe {
+            device.cmd_end_render_pass(command_buffer);
+            device.end_command_buffer(command_buffer).result()
+        }
+        .map_err(anyhow::Error::from)
+        .and_then(|_| submit(&[command_buffer]));
+        if submitted.is_err() {
+            submit(&[])?;
+        }
+
+        let swapchains = [self.swapchain];
+        let image_indices = [image_idx];
+        let present_info = khr_swapchain::PresentInfoKHRBuilder::new()
+            .wait_semaphores(&signal_semaphores)
+            .swapchains(&swapchains)
+            .image_indices(&image_indices);
+        let presented = unsafe { device.queue_present_khr(queue, &present_info) };
+
+        self.frame_idx = (self.frame_idx + 1) % N_FRAMES;
+
+        let presented = match presented.raw {
+            vk::Result::ERROR_OUT_OF_DATE_KHR | vk::Result::SUBOPTIMAL_KHR => {
+                self.swapchain_outdated = true;
+                Ok(())
+            }
+            _ => presented.result(),
+        };
+        submitted?;
+        presented?;
+        self._core.check_validation()
+    }
+}
+
+/// Record the draws in `packet` into `command_buffer`, which must be inside a render pass
+/// compatible with the materials used. `descriptor_set` is bound as set 0, and each material's
+/// texture, if it has one in `textures`, as set 1. Draws are sorted by
+/// material to minimize pipeline binds; draws sharing a material keep their relative order.
+/// Nothing is recorded if any draw refers to a material or mesh that does not exist.
+pub fn record_draws(
+    core: &Core,
+    command_buffer: vk::CommandBuffer,
+    descriptor_set: vk::DescriptorSet,
+    materials: &SlotMap<DefaultKey, Material>,
+    meshes: &SlotMap<MeshKey, MeshBundle>,
+    textures: &SlotMap<TextureKey, Texture>,
+    packet: &FramePacket,
+) -> Result<()> {
+    for draw in &packet.draws {
+        ensure!(
+            materials.contains_key(draw.material),
+            "Draw refers to a material which does not exist"
+        );
+        ensure!(
+            meshes.contains_key(draw.mesh),
+            "Draw refers to a mesh which does not exist"
+        );
+    }
+
+    let mut draws: Vec<&DrawCmd> = packet.draws.iter().collect();
+    draws.sort_by_key(|draw| draw.material);
+
+    let _label = core.label(command_buffer, "Draw packet");
+
+    let mut bound_material = None;
+    let mut bound_mesh = None;
+
+    for draw in draws {
+        let material = &materials[draw.material];
+        let mesh = &meshes[draw.mesh];
+        unsafe {
+            if bound_material != Some(draw.material) {
+                core.device.cmd_bind_pipeline(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    material.pipeline,
+                );
+                // All materials share a compatible layout, so set 0 remains bound across binds
+                if bound_material.is_none() {
+                    core.device.cmd_bind_descriptor_sets(
+                        command_buffer,
+                        vk::PipelineBindPoint::GRAPHICS,
+                        material.pipeline_layout,
+                        0,
+                        &[descriptor_set],
+                        &[],
+                    );
+                }
+                if let Some(texture) = material.texture.and_then(|key| textures.get(key)) {
+                    core.device.cmd_bind_descriptor_sets(
+                        command_buffer,
+                        vk::PipelineBindPoint::GRAPHICS,
+                        material.pipeline_layout,
+                        1,
+                        &[texture.descriptor_set],
+                        &[],
+                    );
+                }
+                bound_material = Some(draw.material);
+            }
+
+            if bound_mesh != Some(draw.mesh) {
+                core.device
+                    .cmd_bind_vertex_buffers(command_buffer, 0, &[mesh.vertices], &[0]);
+                core.device.cmd_bind_index_buffer(
+                    command_buffer,
+                    mesh.indices,
+                    0,
+                    vk::IndexType::UINT32,
+                );
+                bound_mesh = Some(draw.mesh);
+            }
+
+            let push_constants = DrawPushConstants {
+                model: draw.transform,
+            };
+            core.device.cmd_push_constants(
+                command_buffer,
+                material.pipeline_layout,
+                vk::ShaderStageFlags::VERTEX,
+                0,
+                std::mem::size_of::<DrawPushConstants>() as u32,
+                &push_constants as *const DrawPushConstants as _,
+            );
+
+            core.device
+                .cmd_draw_indexed(command_buffer, mesh.n_indices, 1, 0, 0, 0);
         }
     }
+
+    Ok(())
+}
+
+impl Drop for Engine {
+    /// Wait for the GPU to finish, then destroy everything the engine owns, including the
+    /// surface it presents to
+    fn drop(&mut self) {
+        let core = self._core.clone();
+        unsafe {
+            let _ = core.device.device_wait_idle();
+            for sync in &mut self.frame_sync {
+                sync.garbage.clear();
+            }
+            self.streaming.clear();
+            self.swapchain_images.clear();
+            core.device
+                .destroy_swapchain_khr(Some(self.swapchain), None);
+            core.instance.destroy_surface_khr(Some(self.surface), None);
+            core.device
+                .destroy_command_pool(Some(self.command_pool), None);
+            core.device
+                .destroy_descriptor_pool(Some(self.descriptor_pool), None);
+            core.device
+                .destroy_descriptor_set_layout(Some(self.descriptor_set_layout), None);
+            core.device
+                .destroy_descriptor_set_layout(Some(self.texture_set_layout), None);
+            core.device
+                .destroy_render_pass(Some(self.render_pass), None);
+        }
+    }
+}
+
+impl Drop for FrameSync {
+    fn drop(&mut self) {
+        unsafe {
+            self._core
+                .device
+                .destroy_semaphore(Some(self.image_available), None);
+            self._core
+                .device
+                .destroy_semaphore(Some(self.semaphore), None);
+            self._core.device.destroy_fence(Some(self.fence), None);
+        }
+    }
+}
+
+impl FrameSync {
+    /// Create semaphores and a fence. The fence starts signalled, as the frame is not in use.
+    pub fn new(core: &SharedCore) -> Result<Self> {
+        let create_info = vk::SemaphoreCreateInfoBuilder::new();
+        let image_available =
+            unsafe { core.device.create_semaphore(&create_info, None, None) }.result()?;
+        let semaphore =
+            unsafe { core.device.create_semaphore(&create_info, None, None) }.result()?;
+
+        let create_info = vk::FenceCreateInfoBuilder::new().flags(vk::FenceCreateFlags::SIGNALED);
+        let fence = unsafe { core.device.create_fence(&create_info, None, None) }.result()?;
+
+        Ok(Self {
+            image_available,
+            semaphore,
+            fence,
+            garbage: Vec::new(),
+            _core: core.clone(),
+        })
+    }
+
+    /// Name the semaphores and fence after `name` for debugging
+    pub fn set_name(&self, name: &str) -> Result<()> {
+        self._core
+            .set_name(self.image_available, &format!("{} image available", name))?;
+        self._core
+            .set_name(self.semaphore, &format!("{} render finished", name))?;
+        self._core.set_name(self.fence, &format!("{} fence", name))
+    }
+}
+
+/// Create a render pass with one color attachment and one depth attachment, both cleared on load.
+/// The color attachment is transitioned to `final_layout` and made visible to transfer reads once
+/// the pass ends.
+pub fn create_render_pass(
+    core: &Core,
+    color_format: vk::Format,
+    final_layout: vk::ImageLayout,
+) -> Result<vk::RenderPass> {
+    let attachments = [
+        vk::AttachmentDescriptionBuilder::new()
+            .format(color_format)
+            .samples(vk::SampleCountFlagBits::_1)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(final_layout),
+        vk::AttachmentDescriptionBuilder::new()
+            .format(DEPTH_FORMAT)
+            .samples(vk::SampleCountFlagBits::_1)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL),
+    ];
+
+    let color_attachment_refs = [vk::AttachmentReferenceBuilder::new()
+        .attachment(0)
+        .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)];
+
+    let depth_attachment_ref = vk::AttachmentReferenceBuilder::new()
+        .attachment(1)
+        .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+
+    let subpasses = [vk::SubpassDescriptionBuilder::new()
+        .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+        .color_attachments(&color_attachment_refs)
+        .depth_stencil_attachment(&depth_attachment_ref)];
+
+    let attachment_stages = vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+        | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS;
+    let dependencies = [
+        vk::SubpassDependencyBuilder::new()
+            .src_subpass(vk::SUBPASS_EXTERNAL)
+            .dst_subpass(0)
+            .src_stage_mask(attachment_stages)
+            .src_access_mask(vk::AccessFlags::empty())
+            .dst_stage_mask(attachment_stages)
+            .dst_access_mask(
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            ),
+        vk::SubpassDependencyBuilder::new()
+            .src_subpass(0)
+            .dst_subpass(vk::SUBPASS_EXTERNAL)
+            .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+            .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags::TRANSFER)
+            .dst_access_mask(vk::AccessFlags::TRANSFER_READ),
+    ];
+
+    let create_info = vk::RenderPassCreateInfoBuilder::new()
+        .attachments(&attachments)
+        .subpasses(&subpasses)
+        .dependencies(&dependencies);
+
+    Ok(unsafe { core.device.create_render_pass(&create_info, None, None) }.result()?)
+}
+
+/// Create a depth image suitable for use with `create_render_pass`
+pub fn create_depth_image(core: &SharedCore, extent: vk::Extent2D) -> Result<Image> {
+    let image = Image::new(
+        core,
+        extent,
+        DEPTH_FORMAT,
+        vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+        vk::ImageAspectFlags::DEPTH,
+    )?;
+    image.set_name("Depth")?;
+    Ok(image)
+}
+
+/// Create a framebuffer for `create_render_pass` from a color view and a depth view
+pub fn create_framebuffer(
+    core: &Core,
+    render_pass: vk::RenderPass,
+    color: vk::ImageView,
+    depth: vk::ImageView,
+    extent: vk::Extent2D,
+) -> Result<vk::Framebuffer> {
+    let attachments = [color, depth];
+    let create_info = vk::FramebufferCreateInfoBuilder::new()
+        .render_pass(render_pass)
+        .attachments(&attachments)
+        .width(extent.width)
+        .height(extent.height)
+        .layers(1);
+    Ok(unsafe { core.device.create_framebuffer(&create_info, None, None) }.result()?)
+}
+
+/// Begin a render pass created with `create_render_pass`, clearing color to `clear_color` and
+/// depth to 1.0. Also sets the viewport and scissor to cover `extent`.
+pub fn begin_render_pass(
+    core: &Core,
+    command_buffer: vk::CommandBuffer,
+    render_pass: vk::RenderPass,
+    framebuffer: vk::Framebuffer,
+    extent: vk::Extent2D,
+    clear_color: [f32; 4],
+) {
+    let clear_values = [
+        vk::ClearValue {
+            color: vk::ClearColorValue {
+                float32: clear_color,
+            },
+        },
+        vk::ClearValue {
+            depth_stencil: vk::ClearDepthStencilValue {
+                depth: 1.0,
+                stencil: 0,
+            },
+        },
+    ];
+
+    let render_area = vk::Rect2D {
+        offset: vk::Offset2D { x: 0, y: 0 },
+        extent,
+    };
+
+    let begin_info = vk::RenderPassBeginInfoBuilder::new()
+        .framebuffer(framebuffer)
+        .render_pass(render_pass)
+        .render_area(render_area)
+        .clear_values(&clear_values);
+
+    let viewports = [vk::ViewportBuilder::new()
+        .x(0.0)
+        .y(0.0)
+        .width(extent.width as f32)
+        .height(extent.height as f32)
+        .min_depth(0.0)
+        .max_depth(1.0)];
+
+    let scissors = [vk::Rect2DBuilder::new()
+        .offset(render_area.offset)
+        .extent(render_area.extent)];
+
+    unsafe {
+        core.device
+            .cmd_begin_render_pass(command_buffer, &begin_info, vk::SubpassContents::INLINE);
+        core.device.cmd_set_viewport(command_buffer, 0, &viewports);
+        core.device.cmd_set_scissor(command_buffer, 0, &scissors);
+    }
 }
@@ -1,8 +1,97 @@
+use crate::default_engine::Engine;
 use crate::*;
+use anyhow::Result;
+use winit::{
+    dpi::PhysicalSize,
+    window::{Fullscreen, Window},
+};
 
 /// Windowed or fullscreen mode single 2D window
 pub struct FlatEngine {
-    pub inner::Engine,
+    pub inner: Engine,
 }
 
-pub fn new()
+impl FlatEngine {
+    /// Find hardware for `window` and create an engine presenting to it
+    pub fn new(
+        app_info: &ApplicationInfo,
+        setup: &mut VulkanSetup,
+        window: &Window,
+    ) -> Result<Self> {
+        let (surface, hardware, surface_info, core) = windowed::basics(app_info, setup, window)?;
+        let inner = Engine::new(
+            core,
+            hardware,
+            surface,
+            surface_info,
+            extent_from_size(window.inner_size()),
+        )?;
+        Ok(Self { inner })
+    }
+
+    /// Begin recording a frame; see `Engine::begin_frame`
+    pub fn begin_frame(&mut self) -> Result<Option<vk::CommandBuffer>> {
+        self.inner.begin_frame()
+    }
+
+    /// Begin recording a frame after recording `pre_pass`; see `Engine::begin_frame_with`
+    pub fn begin_frame_with(
+        &mut self,
+        pre_pass: impl FnOnce(vk::CommandBuffer),
+    ) -> Result<Option<vk::CommandBuffer>> {
+        self.inner.begin_frame_with(pre_pass)
+    }
+
+    /// Submit and present the frame; see `Engine::end_frame`
+    pub fn end_frame(&mut self) -> Result<()> {
+        self.inner.end_frame()
+    }
+
+    /// Record and present a single frame; see `Engine::frame`
+    pub fn frame(&mut self, record: impl FnOnce(vk::CommandBuffer)) -> Result<()> {
+        self.inner.frame(record)
+    }
+
+    /// Switch vsync on or off; see `Engine::set_vsync`
+    pub fn set_vsync(&mut self, vsync: bool) -> Result<()> {
+        self.inner.set_vsync(vsync)
+    }
+
+    /// Limit the frame rate; see `Engine::set_target_fps`
+    pub fn set_target_fps(&mut self, fps: Option<f32>) {
+        self.inner.set_target_fps(fps)
+    }
+
+    /// Rebuild the swapchain to match the new size of the window before the next frame
+    pub fn resize(&mut self, size: PhysicalSize<u32>) {
+        self.inner.resize(extent_from_size(size))
+    }
+
+    /// Switch between borderless fullscreen on the window's current monitor and windowed mode.
+    /// The window will be resized as a result, which must be passed on to `resize()`.
+    pub fn set_fullscreen(&mut self, window: &Window, fullscreen: bool) {
+        window.set_fullscreen(if fullscreen {
+            Some(Fullscreen::Borderless(window.current_monitor()))
+        } else {
+            None
+        });
+    }
+
+    /// Whether `window` is currently fullscreen
+    pub fn is_fullscreen(&self, window: &Window) -> bool {
+        window.fullscreen().is_some()
+    }
+
+    /// Toggle between fullscreen and windowed mode; see `set_fullscreen`
+    pub fn toggle_fullscreen(&mut self, window: &Window) {
+        let fullscreen = !self.is_fullscreen(window);
+        self.set_fullscreen(window, fullscreen);
+    }
+}
+
+fn extent_from_size(size: PhysicalSize<u32>) -> vk::Extent2D {
+    vk::Extent2D {
+        width: size.width,
+        height: size.height,
+    }
+}
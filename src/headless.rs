@@ -0,0 +1,99 @@
+use crate::debug::DebugMessages;
+use crate::windowed::hardware::{find_utility_queue_family, score_hardware_config, DeviceSelector};
+use crate::*;
+use anyhow::{format_err, Result};
+use erupt::{EntryLoader, InstanceLoader};
+use std::os::raw::c_char;
+use std::sync::Arc;
+
+/// Finds a GRAPHICS queue that the device supports. Presentation support is not required.
+pub fn find_graphics_queue_family(
+    instance: &InstanceLoader,
+    physical_device: vk::PhysicalDevice,
+) -> Result<u32> {
+    let qf_properties =
+        unsafe { instance.get_physical_device_queue_family_properties(physical_device, None) };
+    qf_properties
+        .into_iter()
+        .position(|properties| properties.queue_flags.contains(vk::QueueFlags::GRAPHICS))
+        .map(|i| i as u32)
+        .ok_or_else(|| format_err!("No suitable graphics queue family found"))
+}
+
+/// Select hardware given a physical device
+pub fn select_hardware_physical_device(
+    instance: &InstanceLoader,
+    physical_device: vk::PhysicalDevice,
+) -> Result<HardwareSelection> {
+    Ok(HardwareSelection {
+        physical_device,
+        graphics_queue_family: find_graphics_queue_family(instance, physical_device)?,
+        utility_queue_family: find_utility_queue_family(instance, physical_device)?,
+        physical_device_properties: unsafe {
+            instance.get_physical_device_properties(physical_device, None)
+        },
+    })
+}
+
+/// Query for hardware with the right properties for offscreen rendering, chosen by `selector`.
+/// CPU devices are allowed unless `selector` disallows them.
+pub fn query(
+    instance: &InstanceLoader,
+    device_extensions: &[*const c_char],
+    selector: &DeviceSelector,
+) -> Result<HardwareSelection> {
+    selector.clone().allow_cpu_by_default(true).select(
+        instance,
+        device_extensions,
+        |physical_device| select_hardware_physical_device(instance, physical_device),
+        score_hardware_config,
+    )
+}
+
+/// Find appropriate hardware, and create a core without any surface or swapchain. Suitable for
+/// machines without a display, including those using a software implementation such as lavapipe
+/// unless `setup.device_selector` disallows CPU devices.
+pub fn basics(
+    app_info: &ApplicationInfo,
+    setup: &VulkanSetup,
+) -> Result<(HardwareSelection, SharedCore)> {
+    // Entry
+    let entry = EntryLoader::new()?;
+
+    // Instance
+    let debug_messages = Arc::new(DebugMessages::new(setup.panic_on_validation_error));
+    let instance = crate::core::create_instance(&entry, app_info, setup, &debug_messages)?;
+    let debug_messenger =
+        match crate::debug::create_debug_messenger(&instance, setup, &debug_messages) {
+            Ok(debug_messenger) => debug_messenger,
+            Err(e) => {
+                unsafe { crate::core::destroy_instance(&instance, None) };
+                return Err(e);
+            }
+        };
+
+    // Hardware selection
+    let selector = setup
+        .device_selector
+        .clone()
+        .require_features(setup.required_features);
+    let hardware = match query(&instance, &setup.device_extensions, &selector) {
+        Ok(hardware) => hardware,
+        Err(e) => {
+            unsafe { crate::core::destroy_instance(&instance, debug_messenger) };
+            return Err(e);
+        }
+    };
+
+    // Create Core, which destroys the instance if it fails
+    let core = Core::new(
+        entry,
+        instance,
+        debug_messenger,
+        debug_messages,
+        &hardware,
+        setup,
+    )?;
+
+    Ok((hardware, core))
+}
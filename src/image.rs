@@ -0,0 +1,112 @@
+use crate::*;
+use anyhow::Result;
+use std::mem::ManuallyDrop;
+
+impl Image {
+    /// Allocate a device-local 2D image with a single mip level and a view over the whole of it
+    pub fn new(
+        core: &SharedCore,
+        extent: vk::Extent2D,
+        format: vk::Format,
+        usage: vk::ImageUsageFlags,
+        aspect_mask: vk::ImageAspectFlags,
+    ) -> Result<Self> {
+        Self::with_mip_levels(core, extent, format, usage, aspect_mask, 1)
+    }
+
+    /// Like `new`, with `mip_levels` mip levels, all covered by the view
+    pub fn with_mip_levels(
+        core: &SharedCore,
+        extent: vk::Extent2D,
+        format: vk::Format,
+        usage: vk::ImageUsageFlags,
+        aspect_mask: vk::ImageAspectFlags,
+        mip_levels: u32,
+    ) -> Result<Self> {
+        let create_info = vk::ImageCreateInfoBuilder::new()
+            .image_type(vk::ImageType::_2D)
+            .extent(vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            })
+            .mip_levels(mip_levels)
+            .array_layers(1)
+            .format(format)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .usage(usage)
+            .samples(vk::SampleCountFlagBits::_1)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+
+        let image = unsafe { core.device.create_image(&create_info, None, None) }.result()?;
+        let memory = match core.allocate(crate::memory::image_memory_req(
+            core,
+            image,
+            gpu_alloc::UsageFlags::FAST_DEVICE_ACCESS,
+        )) {
+            Ok(memory) => memory,
+            Err(e) => {
+                unsafe { core.device.destroy_image(Some(image), None) };
+                return Err(e);
+            }
+        };
+
+        let view = (|| {
+            unsafe {
+                core.device
+                    .bind_image_memory(image, *memory.memory(), memory.offset())
+                    .result()?;
+            }
+
+            let create_info = vk::ImageViewCreateInfoBuilder::new()
+                .image(image)
+                .view_type(vk::ImageViewType::_2D)
+                .format(format)
+                .subresource_range(
+                    vk::ImageSubresourceRangeBuilder::new()
+                        .aspect_mask(aspect_mask)
+                        .base_mip_level(0)
+                        .level_count(mip_levels)
+                        .base_array_layer(0)
+                        .layer_count(1)
+                        .build(),
+                );
+            Ok(unsafe { core.device.create_image_view(&create_info, None, None) }.result()?)
+        })();
+        let view = match view {
+            Ok(view) => view,
+            Err(e) => {
+                unsafe { core.device.destroy_image(Some(image), None) };
+                let _ = core.deallocate(memory);
+                return Err(e);
+            }
+        };
+
+        Ok(Self {
+            image,
+            view,
+            extent,
+            mip_levels,
+            memory: ManuallyDrop::new(memory),
+            _core: core.clone(),
+        })
+    }
+
+    /// Name the image and its view after `name` for debugging
+    pub fn set_name(&self, name: &str) -> Result<()> {
+        self._core.set_name(self.image, name)?;
+        self._core.set_name(self.view, name)
+    }
+}
+
+impl Drop for Image {
+    /// Destroy the view and image, and free the backing memory
+    fn drop(&mut self) {
+        unsafe {
+            self._core.device.destroy_image_view(Some(self.view), None);
+            self._core.device.destroy_image(Some(self.image), None);
+            let _ = self._core.deallocate(ManuallyDrop::take(&mut self.memory));
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use crate::*;
+use ::image::ImageFormat;
+use anyhow::{bail, Context, Result};
+use std::path::Path;
+
+/// Decode a PNG or JPEG file held in memory into tightly packed RGBA8 rows, top to bottom
+pub fn decode_rgba8(bytes: &[u8]) -> Result<(vk::Extent2D, Vec<u8>)> {
+    let format = ::image::guess_format(bytes).context("Unrecognized image format")?;
+    if !matches!(format, ImageFormat::Png | ImageFormat::Jpeg) {
+        bail!(
+            "Unsupported image format {:?}; expected PNG or JPEG",
+            format
+        );
+    }
+    let image = ::image::load_from_memory_with_format(bytes, format)?.to_rgba8();
+    let extent = vk::Extent2D {
+        width: image.width(),
+        height: image.height(),
+    };
+    Ok((extent, image.into_raw()))
+}
+
+/// Read and decode a PNG or JPEG file; see `decode_rgba8`
+pub fn load_rgba8(path: impl AsRef<Path>) -> Result<(vk::Extent2D, Vec<u8>)> {
+    let path = path.as_ref();
+    let bytes =
+        std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    decode_rgba8(&bytes).with_context(|| format!("Failed to decode {}", path.display()))
+}
+
+/// Format of RGBA8 texture data. Color data such as albedo is usually sRGB encoded, while data
+/// such as normal maps is linear.
+pub fn rgba8_format(srgb: bool) -> vk::Format {
+    if srgb {
+        vk::Format::R8G8B8A8_SRGB
+    } else {
+        vk::Format::R8G8B8A8_UNORM
+    }
+}
+
+impl Texture {
+    /// Decode a PNG or JPEG file held in memory and upload it with a full mip chain; see
+    /// `Texture::new`. `srgb` selects whether the texels are sampled as sRGB or linear.
+    pub fn from_memory(
+        core: &SharedCore,
+        bytes: &[u8],
+        srgb: bool,
+        set_layout: vk::DescriptorSetLayout,
+    ) -> Result<Self> {
+        let (extent, pixels) = decode_rgba8(bytes)?;
+        Self::new(
+            core,
+            extent.width,
+            extent.height,
+            rgba8_format(srgb),
+            &pixels,
+            true,
+            set_layout,
+        )
+    }
+
+    /// Read a PNG or JPEG file from disk and upload it, named after its path; see `from_memory`
+    pub fn from_file(
+        core: &SharedCore,
+        path: impl AsRef<Path>,
+        srgb: bool,
+        set_layout: vk::DescriptorSetLayout,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        let (extent, pixels) = load_rgba8(path)?;
+        let texture = Self::new(
+            core,
+            extent.width,
+            extent.height,
+            rgba8_format(srgb),
+            &pixels,
+            true,
+            set_layout,
+        )?;
+        texture.set_name(&path.display().to_string())?;
+        Ok(texture)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RGB_PNG: &[u8] = include_bytes!("test_data/rgb_3x2.png");
+    const GREY_PNG: &[u8] = include_bytes!("test_data/grey_2x2.png");
+    const RGB_JPEG: &[u8] = include_bytes!("test_data/rgb_8x8.jpg");
+
+    #[test]
+    fn rgb_png_expands_to_rgba() -> Result<()> {
+        let (extent, pixels) = decode_rgba8(RGB_PNG)?;
+        assert_eq!((extent.width, extent.height), (3, 2));
+        assert_eq!(pixels.len(), 3 * 2 * 4);
+        // Second row, last column
+        assert_eq!(&pixels[5 * 4..], &[200, 200, 7, 255]);
+        Ok(())
+    }
+
+    #[test]
+    fn grey_png_expands_to_rgba() -> Result<()> {
+        let (extent, pixels) = decode_rgba8(GREY_PNG)?;
+        assert_eq!((extent.width, extent.height), (2, 2));
+        let expected = [0, 80, 160, 240]
+            .iter()
+            .flat_map(|&l| vec![l, l, l, 255])
+            .collect::<Vec<u8>>();
+        assert_eq!(pixels, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn jpeg_decodes() -> Result<()> {
+        let (extent, pixels) = decode_rgba8(RGB_JPEG)?;
+        assert_eq!((extent.width, extent.height), (8, 8));
+        assert_eq!(pixels.len(), 8 * 8 * 4);
+        // Lossy, so only approximately the encoded color
+        let texel = &pixels[..4];
+        for (&actual, &expected) in texel.iter().zip(&[200u8, 100, 50, 255]) {
+            assert!((actual as i32 - expected as i32).abs() <= 4, "{:?}", texel);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn corrupt_bytes_are_rejected() {
+        assert!(decode_rgba8(&RGB_PNG[..RGB_PNG.len() / 2]).is_err());
+        assert!(decode_rgba8(b"not an image").is_err());
+        assert!(decode_rgba8(b"GIF89a\x01\x00\x01\x00").is_err());
+    }
+
+    #[test]
+    fn format_matches_encoding() {
+        assert_eq!(rgba8_format(true), vk::Format::R8G8B8A8_SRGB);
+        assert_eq!(rgba8_format(false), vk::Format::R8G8B8A8_UNORM);
+    }
+}
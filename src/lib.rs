@@ -1,26 +1,67 @@
 pub use erupt::vk1_0 as vk;
+use slotmap::DefaultKey;
+use std::mem::ManuallyDrop;
+pub mod compute;
 mod core;
 pub use crate::core::*;
+pub mod debug;
 pub mod default_engine;
+pub mod flat;
+pub mod headless;
+mod image;
+pub mod image_file;
+mod material;
+mod mesh;
+pub mod obj;
+pub mod offscreen;
+pub mod pacing;
+pub mod scene;
+mod setup;
+mod texture;
+pub use setup::VulkanSetupBuilder;
 pub mod windowed;
 pub mod mem_objects;
 pub mod memory;
+pub mod transfer;
 
 pub const ENGINE_NAME: &str = "Klystron II";
 
 /// Data associated with CPU-GPU and GPU-GPU synchronization.
 /// These synchronization primitives are signalled when a frame finishes.
 pub struct FrameSync {
+    /// Signalled when the swapchain image for this frame has been acquired
+    pub image_available: vk::Semaphore,
     pub semaphore: vk::Semaphore,
     pub fence: vk::Fence,
+    /// Resources to be dropped once `fence` signals; see `Engine::defer_drop`
+    pub garbage: Vec<Box<dyn std::any::Any>>,
     _core: SharedCore,
 }
 
+slotmap::new_key_type! {
+    /// Key of a `MeshBundle` belonging to an engine
+    pub struct MeshKey;
+}
+
+/// Standard vertex layout produced by the mesh loaders; see `Vertex::attributes`
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Vertex {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    /// Texture coordinates, with v increasing from the top of the image
+    pub uv: [f32; 2],
+}
+
+unsafe impl bytemuck::Zeroable for Vertex {}
+unsafe impl bytemuck::Pod for Vertex {}
+
 /// A set of meshes which are allocated and deallocated together
 pub struct MeshBundle {
     pub vertices: vk::Buffer,
     pub indices: vk::Buffer,
-    pub memory: Memory,
+    pub n_indices: u32,
+    pub memory: ManuallyDrop<Memory>,
     _core: SharedCore,
 }
 
@@ -29,6 +70,90 @@ pub struct MeshBundle {
 pub struct Material {
     pub pipeline: vk::Pipeline,
     pub pipeline_layout: vk::PipelineLayout,
+    /// Texture bound at set 1, if any
+    pub texture: Option<TextureKey>,
+    _core: SharedCore,
+}
+
+/// A compute pipeline with its own descriptor set layout, and a pool of sets for it
+pub struct ComputeKernel {
+    pub pipeline: vk::Pipeline,
+    pub pipeline_layout: vk::PipelineLayout,
+    pub descriptor_set_layout: vk::DescriptorSetLayout,
+    pub descriptor_pool: vk::DescriptorPool,
+    /// Type of each binding in set 0
+    pub bindings: Vec<vk::DescriptorType>,
+    pub push_constant_size: u32,
+    _core: SharedCore,
+}
+
+/// Fixed-function state used to build a `Material`'s pipeline
+#[derive(Clone, Debug)]
+pub struct MaterialDesc {
+    pub topology: vk::PrimitiveTopology,
+    pub cull_mode: vk::CullModeFlags,
+    pub front_face: vk::FrontFace,
+    /// `LINE` renders wireframe, and requires the `fill_mode_non_solid` device feature; see
+    /// `VulkanSetup::required_features`
+    pub polygon_mode: vk::PolygonMode,
+    pub depth_test: bool,
+    pub depth_write: bool,
+    /// Standard alpha blending; `src * src_alpha + dst * (1 - src_alpha)`
+    pub blend: bool,
+    /// Format of each vertex attribute, in order of location. Attributes are tightly packed
+    /// together in vertex buffer binding 0.
+    pub vertex_attributes: Vec<vk::Format>,
+}
+
+impl Default for MaterialDesc {
+    /// Opaque, depth tested and back face culled triangles, with position and color attributes
+    fn default() -> Self {
+        Self {
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            cull_mode: vk::CullModeFlags::BACK,
+            front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+            polygon_mode: vk::PolygonMode::FILL,
+            depth_test: true,
+            depth_write: true,
+            blend: false,
+            vertex_attributes: vec![vk::Format::R32G32B32_SFLOAT, vk::Format::R32G32B32_SFLOAT],
+        }
+    }
+}
+
+/// A single draw of a mesh using a material
+#[derive(Clone, Copy, Debug)]
+pub struct DrawCmd {
+    pub material: DefaultKey,
+    pub mesh: MeshKey,
+    /// Column-major model matrix
+    pub transform: [f32; 16],
+}
+
+/// Everything to be drawn in a single frame
+#[derive(Clone, Debug)]
+pub struct FramePacket {
+    /// Column-major view matrix
+    pub view: [f32; 16],
+    /// Column-major projection matrix
+    pub projection: [f32; 16],
+    /// Time in seconds, made available to shaders
+    pub time: f32,
+    pub draws: Vec<DrawCmd>,
+}
+
+slotmap::new_key_type! {
+    /// Key of a `Texture` belonging to an engine
+    pub struct TextureKey;
+}
+
+/// A sampled image, with a descriptor set binding it as a combined image sampler
+pub struct Texture {
+    pub image: Image,
+    pub sampler: vk::Sampler,
+    pub descriptor_pool: vk::DescriptorPool,
+    /// Binds the texture at binding 0
+    pub descriptor_set: vk::DescriptorSet,
     _core: SharedCore,
 }
 
@@ -37,6 +162,8 @@ pub struct Image {
     pub image: vk::Image,
     pub view: vk::ImageView,
     pub extent: vk::Extent2D,
+    pub mip_levels: u32,
+    pub memory: ManuallyDrop<Memory>,
     _core: SharedCore,
 }
 
@@ -44,8 +171,10 @@ pub struct Image {
 /// as well as a fence to wait until it is unused.
 pub struct SwapchainImage {
     pub view: vk::ImageView,
+    pub framebuffer: vk::Framebuffer,
     pub extent: vk::Extent2D,
-    /// Signalled when this image is unused
+    /// Signalled when this image is unused. This is the fence of the last frame
+    /// to render to this image, or null if it has not been rendered to yet.
     pub fence: vk::Fence,
     _core: SharedCore,
 }
@@ -59,27 +188,27 @@ pub struct HardwareSelection {
     pub utility_queue_family: u32,
 }
 
-/// Set of Vulkan layers, extensions, and version
+/// Set of Vulkan layers, extensions, and version. Usually created with `VulkanSetup::builder()`.
 pub struct VulkanSetup {
     pub instance_layers: Vec<*const i8>,
     pub instance_extensions: Vec<*const i8>,
     pub device_layers: Vec<*const i8>,
+    /// Device extensions which must be supported
     pub device_extensions: Vec<*const i8>,
+    /// Device extensions enabled only if supported
+    pub optional_device_extensions: Vec<*const i8>,
+    /// Device features which must be supported
+    pub required_features: vk::PhysicalDeviceFeatures,
+    /// Device features enabled only if supported. See `Core::enabled_features`.
+    pub optional_features: vk::PhysicalDeviceFeatures,
     pub api_version: u32,
-}
-
-impl VulkanSetup {
-    pub fn validation(api_version: u32) -> Self {
-        const LAYER_KHRONOS_VALIDATION: *const i8 = erupt::cstr!("VK_LAYER_KHRONOS_validation");
-        use erupt::extensions::ext_debug_utils::EXT_DEBUG_UTILS_EXTENSION_NAME;
-        VulkanSetup {
-            instance_layers: vec![LAYER_KHRONOS_VALIDATION],
-            instance_extensions: vec![EXT_DEBUG_UTILS_EXTENSION_NAME],
-            device_layers: vec![LAYER_KHRONOS_VALIDATION],
-            device_extensions: vec![],
-            api_version,
-        }
-    }
+    /// Record validation errors, which fail the next queue submission (see
+    /// `Core::check_validation`) or panic when the core is dropped
+    pub panic_on_validation_error: bool,
+    /// Policy for choosing the physical device
+    pub device_selector: windowed::hardware::DeviceSelector,
+    /// Preferred swapchain format and present mode, for windowed engines
+    pub surface_preferences: windowed::hardware::SurfacePreferences,
 }
 
 impl Default for VulkanSetup {
@@ -90,7 +219,13 @@ impl Default for VulkanSetup {
             instance_extensions: Vec::new(),
             device_layers: Vec::new(),
             device_extensions: Vec::new(),
+            optional_device_extensions: Vec::new(),
+            required_features: Default::default(),
+            optional_features: Default::default(),
             api_version: vk::make_version(1, 0, 0),
+            panic_on_validation_error: false,
+            device_selector: Default::default(),
+            surface_preferences: Default::default(),
         }
     }
 }
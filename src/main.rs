@@ -1,14 +1,37 @@
 use wibaeowibtnr::*;
 use anyhow::Result;
+use winit::{
+    event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent},
+    event_loop::ControlFlow,
+};
 
 fn main() -> Result<()> {
     let app_info = ApplicationInfo {
         name: "Test app".into(),
         version: vk::make_version(1, 0, 0),
     };
-    let mut setup = default_engine::vk_setup(true);
+    let mut setup = VulkanSetup::builder().validation(true).build()?;
     let event_loop = winit::event_loop::EventLoop::new();
     let window = winit::window::Window::new(&event_loop)?;
-    let _ = windowed::basics(&app_info, &mut setup, &window)?;
-    Ok(())
+    let mut engine = flat::FlatEngine::new(&app_info, &mut setup, &window)?;
+
+    event_loop.run(move |event, _, control_flow| match event {
+        Event::WindowEvent { event, .. } => match event {
+            WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
+            WindowEvent::Resized(size) => engine.resize(size),
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        virtual_keycode: Some(VirtualKeyCode::F11),
+                        state: ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => engine.toggle_fullscreen(&window),
+            _ => (),
+        },
+        Event::MainEventsCleared => window.request_redraw(),
+        Event::RedrawRequested(_) => engine.frame(|_| ()).unwrap(),
+        _ => (),
+    })
 }
@@ -0,0 +1,230 @@
+use crate::default_engine::DrawPushConstants;
+use crate::*;
+use anyhow::{ensure, format_err, Result};
+use std::ffi::CString;
+
+impl Material {
+    /// Build a graphics pipeline from SPIR-V vertex and fragment shaders (both with entry point
+    /// `main`), compatible with `render_pass` and using `descriptor_set_layouts` as sets 0 and
+    /// up. In the default engine, set 0 holds the `CameraUniforms` block at binding 0, and set 1
+    /// the material's texture as a combined image sampler at binding 0. `DrawPushConstants` are
+    /// available to the vertex stage. Viewport and scissor are dynamic state.
+    pub fn new(
+        core: &SharedCore,
+        render_pass: vk::RenderPass,
+        descriptor_set_layouts: &[vk::DescriptorSetLayout],
+        vertex_spv: &[u8],
+        fragment_spv: &[u8],
+        desc: &MaterialDesc,
+    ) -> Result<Self> {
+        ensure!(
+            desc.polygon_mode == vk::PolygonMode::FILL
+                || core.enabled_features.fill_mode_non_solid != vk::FALSE,
+            "Polygon mode {:?} requires the fill_mode_non_solid device feature",
+            desc.polygon_mode
+        );
+        let vertex = shader_module(core, vertex_spv)?;
+        let fragment = match shader_module(core, fragment_spv) {
+            Ok(fragment) => fragment,
+            Err(e) => {
+                unsafe { core.device.destroy_shader_module(Some(vertex), None) };
+                return Err(e);
+            }
+        };
+        let result = Self::from_modules(
+            core,
+            render_pass,
+            descriptor_set_layouts,
+            vertex,
+            fragment,
+            desc,
+        );
+        unsafe {
+            core.device.destroy_shader_module(Some(vertex), None);
+            core.device.destroy_shader_module(Some(fragment), None);
+        }
+        result
+    }
+
+    fn from_modules(
+        core: &SharedCore,
+        render_pass: vk::RenderPass,
+        descriptor_set_layouts: &[vk::DescriptorSetLayout],
+        vertex: vk::ShaderModule,
+        fragment: vk::ShaderModule,
+        desc: &MaterialDesc,
+    ) -> Result<Self> {
+        // Vertex input
+        let mut attributes = Vec::with_capacity(desc.vertex_attributes.len());
+        let mut stride = 0;
+        for (location, &format) in desc.vertex_attributes.iter().enumerate() {
+            attributes.push(
+                vk::VertexInputAttributeDescriptionBuilder::new()
+                    .binding(0)
+                    .location(location as u32)
+                    .format(format)
+                    .offset(stride),
+            );
+            stride += format_size(format)
+                .ok_or_else(|| format_err!("Unsupported vertex attribute format {:?}", format))?;
+        }
+        let bindings = [vk::VertexInputBindingDescriptionBuilder::new()
+            .binding(0)
+            .stride(stride)
+            .input_rate(vk::VertexInputRate::VERTEX)];
+        let vertex_input = vk::PipelineVertexInputStateCreateInfoBuilder::new()
+            .vertex_binding_descriptions(&bindings)
+            .vertex_attribute_descriptions(&attributes);
+
+        let input_assembly = vk::PipelineInputAssemblyStateCreateInfoBuilder::new()
+            .topology(desc.topology)
+            .primitive_restart_enable(false);
+
+        // Viewport and scissor are set when the render pass begins
+        let viewport_state = vk::PipelineViewportStateCreateInfoBuilder::new()
+            .viewport_count(1)
+            .scissor_count(1);
+        let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        let dynamic_state =
+            vk::PipelineDynamicStateCreateInfoBuilder::new().dynamic_states(&dynamic_states);
+
+        let rasterizer = vk::PipelineRasterizationStateCreateInfoBuilder::new()
+            .depth_clamp_enable(false)
+            .rasterizer_discard_enable(false)
+            .polygon_mode(desc.polygon_mode)
+            .line_width(1.0)
+            .cull_mode(desc.cull_mode)
+            .front_face(desc.front_face);
+
+        let multisampling = vk::PipelineMultisampleStateCreateInfoBuilder::new()
+            .sample_shading_enable(false)
+            .rasterization_samples(vk::SampleCountFlagBits::_1);
+
+        let depth_stencil = vk::PipelineDepthStencilStateCreateInfoBuilder::new()
+            .depth_test_enable(desc.depth_test)
+            .depth_write_enable(desc.depth_write)
+            .depth_compare_op(vk::CompareOp::LESS)
+            .depth_bounds_test_enable(false)
+            .stencil_test_enable(false);
+
+        let color_blend_attachments = [vk::PipelineColorBlendAttachmentStateBuilder::new()
+            .color_write_mask(
+                vk::ColorComponentFlags::R
+                    | vk::ColorComponentFlags::G
+                    | vk::ColorComponentFlags::B
+                    | vk::ColorComponentFlags::A,
+            )
+            .blend_enable(desc.blend)
+            .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
+            .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+            .color_blend_op(vk::BlendOp::ADD)
+            .src_alpha_blend_factor(vk::BlendFactor::ONE)
+            .dst_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+            .alpha_blend_op(vk::BlendOp::ADD)];
+        let color_blending = vk::PipelineColorBlendStateCreateInfoBuilder::new()
+            .logic_op_enable(false)
+            .attachments(&color_blend_attachments);
+
+        let entry_point = CString::new("main")?;
+        let shader_stages = [
+            vk::PipelineShaderStageCreateInfoBuilder::new()
+                .stage(vk::ShaderStageFlagBits::VERTEX)
+                .module(vertex)
+                .name(&entry_point),
+            vk::PipelineShaderStageCreateInfoBuilder::new()
+                .stage(vk::ShaderStageFlagBits::FRAGMENT)
+                .module(fragment)
+                .name(&entry_point),
+        ];
+
+        let push_constant_ranges = [vk::PushConstantRangeBuilder::new()
+            .stage_flags(vk::ShaderStageFlags::VERTEX)
+            .offset(0)
+            .size(std::mem::size_of::<DrawPushConstants>() as u32)];
+        let create_info = vk::PipelineLayoutCreateInfoBuilder::new()
+            .set_layouts(descriptor_set_layouts)
+            .push_constant_ranges(&push_constant_ranges);
+        let pipeline_layout =
+            unsafe { core.device.create_pipeline_layout(&create_info, None, None) }.result()?;
+
+        let create_info = vk::GraphicsPipelineCreateInfoBuilder::new()
+            .stages(&shader_stages)
+            .vertex_input_state(&vertex_input)
+            .input_assembly_state(&input_assembly)
+            .viewport_state(&viewport_state)
+            .rasterization_state(&rasterizer)
+            .multisample_state(&multisampling)
+            .depth_stencil_state(&depth_stencil)
+            .color_blend_state(&color_blending)
+            .dynamic_state(&dynamic_state)
+            .layout(pipeline_layout)
+            .render_pass(render_pass)
+            .subpass(0);
+
+        let pipeline = match unsafe {
+            core.device
+                .create_graphics_pipelines(None, &[create_info], None)
+        }
+        .result()
+        {
+            Ok(pipelines) => pipelines[0],
+            Err(e) => {
+                unsafe {
+                    core.device
+                        .destroy_pipeline_layout(Some(pipeline_layout), None);
+                }
+                return Err(e.into());
+            }
+        };
+
+        Ok(Self {
+            pipeline,
+            pipeline_layout,
+            texture: None,
+            _core: core.clone(),
+        })
+    }
+
+    /// Name the pipeline and its layout after `name` for debugging
+    pub fn set_name(&self, name: &str) -> Result<()> {
+        self._core.set_name(self.pipeline, name)?;
+        self._core.set_name(self.pipeline_layout, name)
+    }
+}
+
+impl Drop for Material {
+    /// Destroy the pipeline and its layout. The GPU must be finished using them.
+    fn drop(&mut self) {
+        unsafe {
+            self._core
+                .device
+                .destroy_pipeline(Some(self.pipeline), None);
+            self._core
+                .device
+                .destroy_pipeline_layout(Some(self.pipeline_layout), None);
+        }
+    }
+}
+
+/// Create a shader module from SPIR-V bytes
+pub fn shader_module(core: &Core, spv: &[u8]) -> Result<vk::ShaderModule> {
+    let code = erupt::utils::decode_spv(spv)?;
+    let create_info = vk::ShaderModuleCreateInfoBuilder::new().code(&code);
+    Ok(unsafe { core.device.create_shader_module(&create_info, None, None) }.result()?)
+}
+
+/// Size in bytes of a single vertex attribute of the given format
+fn format_size(format: vk::Format) -> Option<u32> {
+    Some(match format {
+        vk::Format::R32_SFLOAT | vk::Format::R32_UINT | vk::Format::R32_SINT => 4,
+        vk::Format::R8G8B8A8_UNORM | vk::Format::R8G8B8A8_UINT => 4,
+        vk::Format::R32G32_SFLOAT | vk::Format::R32G32_UINT | vk::Format::R32G32_SINT => 8,
+        vk::Format::R32G32B32_SFLOAT | vk::Format::R32G32B32_UINT | vk::Format::R32G32B32_SINT => {
+            12
+        }
+        vk::Format::R32G32B32A32_SFLOAT
+        | vk::Format::R32G32B32A32_UINT
+        | vk::Format::R32G32B32A32_SINT => 16,
+        _ => return None,
+    })
+}
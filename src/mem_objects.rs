@@ -1,27 +1,71 @@
-use crate::{Core, Memory};
-use anyhow::Result;
-use drop_bomb::DropBomb;
+use crate::{Core, Memory, SharedCore};
+use anyhow::{ensure, format_err, Result};
+use bytemuck::Pod;
 use erupt::vk1_0 as vk;
+use gpu_alloc::MemoryPropertyFlags;
+use gpu_alloc_erupt::EruptMemoryDevice as EMD;
+use std::marker::PhantomData;
 
-pub trait Viewable {
+/// Vulkan objects which are backed by memory and may have views (I.E. Buffer, Image)
+pub trait Viewable: Copy {
     type View;
+
+    /// Destroy the object, not including its memory.
+    ///
+    /// # Safety
+    /// The GPU must be finished using the object, and it must not be used again.
+    unsafe fn destroy(self, core: &Core);
+}
+
+impl Viewable for vk::Buffer {
+    type View = vk::BufferView;
+
+    unsafe fn destroy(self, core: &Core) {
+        core.device.destroy_buffer(Some(self), None);
+    }
+}
+
+impl Viewable for vk::Image {
+    type View = vk::ImageView;
+
+    unsafe fn destroy(self, core: &Core) {
+        core.device.destroy_image(Some(self), None);
+    }
 }
 
 /// Memory objects that have attached views. Meant for simple cases where there is only one view
-/// and one memory object (I.E. Buffer, Image)
-pub struct MemObject<T> {
+/// and one memory object (I.E. Buffer, Image). The object is destroyed and its memory freed when
+/// dropped; the GPU must be finished using it by then.
+pub struct MemObject<T: Viewable> {
     pub instance: T,
     memory: Option<Memory>,
-    bomb: DropBomb,
+    core: SharedCore,
 }
 
-impl<T> MemObject<T> {
+impl<T: Viewable> MemObject<T> {
     pub fn memory(&self) -> &Memory {
-        self.memory.as_ref().expect("Use after free")
+        self.memory.as_ref().expect("Memory object has no memory")
     }
 
     pub fn memory_mut(&mut self) -> &mut Memory {
-        self.memory.as_mut().expect("Use after free")
+        self.memory.as_mut().expect("Memory object has no memory")
+    }
+
+    /// Whether the backing memory may be mapped by the host
+    pub fn host_visible(&self) -> bool {
+        self.memory()
+            .props()
+            .contains(MemoryPropertyFlags::HOST_VISIBLE)
+    }
+}
+
+impl<T: Viewable> Drop for MemObject<T> {
+    fn drop(&mut self) {
+        unsafe { self.instance.destroy(&self.core) };
+        if let Some(memory) = self.memory.take() {
+            // Only fails if the allocator mutex is poisoned, in which case the memory is leaked
+            let _ = self.core.deallocate(memory);
+        }
     }
 }
 
@@ -29,31 +73,24 @@ impl MemObject<vk::Image> {
     /// Allocate a new image with the given usage. Note that for the view builder, `image` does not
     /// need to be specified as this method will handle adding it.
     pub fn new(
-        core: &Core,
+        core: &SharedCore,
         create_info: vk::ImageCreateInfoBuilder<'static>,
         usage: gpu_alloc::UsageFlags,
     ) -> Result<Self> {
         let instance = unsafe { core.device.create_image(&create_info, None, None) }.result()?;
-        let memory = core.allocate(crate::memory::image_memory_req(&core, instance, usage))?;
+        let mut object = Self {
+            instance,
+            memory: None,
+            core: core.clone(),
+        };
+        let memory = core.allocate(crate::memory::image_memory_req(core, instance, usage))?;
+        let memory = object.memory.get_or_insert(memory);
         unsafe {
             core.device
                 .bind_image_memory(instance, *memory.memory(), memory.offset())
                 .result()?;
         }
-        Ok(Self {
-            instance,
-            memory: Some(memory),
-            bomb: DropBomb::new("Image memory object dropped without calling free()!"),
-        })
-    }
-
-    pub fn free(&mut self, core: &Core) {
-        unsafe {
-            core.device.destroy_image(Some(self.instance), None);
-            core.deallocate(self.memory.take().expect("Double free of image memory"))
-                .unwrap();
-            self.bomb.defuse();
-        }
+        Ok(object)
     }
 }
 
@@ -61,30 +98,187 @@ impl MemObject<vk::Buffer> {
     /// Allocate a new buffer with the given usage. Note that for the view builder, `buffer` does not
     /// need to be specified as this method will handle adding it.
     pub fn new(
-        core: &Core,
+        core: &SharedCore,
         create_info: vk::BufferCreateInfoBuilder<'static>,
         usage: gpu_alloc::UsageFlags,
     ) -> Result<Self> {
         let instance = unsafe { core.device.create_buffer(&create_info, None, None) }.result()?;
-        let memory = core.allocate(crate::memory::buffer_memory_req(&core, instance, usage))?;
+        let mut object = Self {
+            instance,
+            memory: None,
+            core: core.clone(),
+        };
+        let memory = core.allocate(crate::memory::buffer_memory_req(core, instance, usage))?;
+        let memory = object.memory.get_or_insert(memory);
         unsafe {
             core.device
                 .bind_buffer_memory(instance, *memory.memory(), memory.offset())
                 .result()?;
         }
+        Ok(object)
+    }
+}
+
+/// A buffer holding `len()` elements of `T`, which reallocates to grow. Host-visible buffers are
+/// accessed with `write` and `read`; device-local buffers are filled with `upload`. Freed when
+/// dropped.
+pub struct TypedBuffer<T> {
+    buffer: MemObject<vk::Buffer>,
+    len: usize,
+    capacity: usize,
+    usage: vk::BufferUsageFlags,
+    memory_usage: gpu_alloc::UsageFlags,
+    core: SharedCore,
+    _marker: PhantomData<T>,
+}
+
+impl<T: Pod> TypedBuffer<T> {
+    /// Create an empty buffer with room for `capacity` elements. Transfer usage is always added
+    /// to `usage`, so that the buffer may be uploaded to and grown.
+    pub fn new(
+        core: &SharedCore,
+        capacity: usize,
+        usage: vk::BufferUsageFlags,
+        memory_usage: gpu_alloc::UsageFlags,
+    ) -> Result<Self> {
+        let usage = usage | vk::BufferUsageFlags::TRANSFER_SRC | vk::BufferUsageFlags::TRANSFER_DST;
         Ok(Self {
-            instance,
-            memory: Some(memory),
-            bomb: DropBomb::new("Buffer memory object dropped without calling free()!"),
+            buffer: Self::allocate(core, capacity, usage, memory_usage)?,
+            len: 0,
+            capacity,
+            usage,
+            memory_usage,
+            core: core.clone(),
+            _marker: PhantomData,
         })
     }
 
-    pub fn free(&mut self, core: &Core) {
+    fn allocate(
+        core: &SharedCore,
+        capacity: usize,
+        usage: vk::BufferUsageFlags,
+        memory_usage: gpu_alloc::UsageFlags,
+    ) -> Result<MemObject<vk::Buffer>> {
+        // Zero-sized buffers are not allowed
+        let size = capacity
+            .checked_mul(std::mem::size_of::<T>())
+            .ok_or_else(|| format_err!("Buffer of {} elements is too large", capacity))?
+            .max(1);
+        let create_info = vk::BufferCreateInfoBuilder::new()
+            .size(size as u64)
+            .usage(usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        MemObject::<vk::Buffer>::new(core, create_info, memory_usage)
+    }
+
+    pub fn buffer(&self) -> vk::Buffer {
+        self.buffer.instance
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Size of the contents in bytes
+    pub fn size_bytes(&self) -> u64 {
+        (self.len * std::mem::size_of::<T>()) as u64
+    }
+
+    pub fn host_visible(&self) -> bool {
+        self.buffer.host_visible()
+    }
+
+    /// Grow the buffer to hold at least `capacity` elements, preserving its contents. The
+    /// contents are copied on the graphics queue, which then owns the new buffer. The GPU must be
+    /// finished using the old buffer.
+    pub fn reserve(&mut self, capacity: usize) -> Result<()> {
+        if capacity <= self.capacity {
+            return Ok(());
+        }
+        let capacity = capacity.max(self.capacity.saturating_mul(2));
+        let buffer = Self::allocate(&self.core, capacity, self.usage, self.memory_usage)?;
+
+        if self.len > 0 {
+            let (src, dst, size) = (self.buffer.instance, buffer.instance, self.size_bytes());
+            let copied = crate::transfer::one_shot(
+                &self.core,
+                self.core.graphics_queue_family,
+                self.core.graphics_queue,
+                |command_buffer| unsafe {
+                    let region = vk::BufferCopyBuilder::new()
+                        .src_offset(0)
+                        .dst_offset(0)
+                        .size(size);
+                    self.core
+                        .device
+                        .cmd_copy_buffer(command_buffer, src, dst, &[region]);
+                },
+            );
+            copied?;
+        }
+
+        self.buffer = buffer;
+        self.capacity = capacity;
+        Ok(())
+    }
+
+    /// Replace the contents with `data` by mapping host-visible memory, growing if needed
+    pub fn write(&mut self, data: &[T]) -> Result<()> {
+        ensure!(
+            self.host_visible(),
+            "Buffer is not host-visible; use upload()"
+        );
+        self.reserve(data.len())?;
+        self.len = 0;
         unsafe {
-            core.device.destroy_buffer(Some(self.instance), None);
-            core.deallocate(self.memory.take().expect("Double free of buffer memory"))
-                .unwrap();
-            self.bomb.defuse();
+            self.buffer.memory_mut().write_bytes(
+                EMD::wrap(&self.core.device),
+                0,
+                bytemuck::cast_slice(data),
+            )?;
+        }
+        self.len = data.len();
+        Ok(())
+    }
+
+    /// Read the contents from host-visible memory. Any GPU writes must have been made visible to
+    /// the host.
+    pub fn read(&mut self) -> Result<Vec<T>> {
+        ensure!(self.host_visible(), "Buffer is not host-visible");
+        let mut data = vec![T::zeroed(); self.len];
+        unsafe {
+            self.buffer.memory_mut().read_bytes(
+                EMD::wrap(&self.core.device),
+                0,
+                bytemuck::cast_slice_mut(&mut data),
+            )?;
+        }
+        Ok(data)
+    }
+
+    /// Replace the contents with `data` through a staging buffer, growing if needed. Blocks
+    /// until the upload completes, after which the buffer is owned by the graphics queue family.
+    /// The GPU must be finished using the buffer.
+    pub fn upload(&mut self, data: &[T]) -> Result<()> {
+        if data.is_empty() {
+            self.len = 0;
+            return Ok(());
         }
+        self.reserve(data.len())?;
+        self.len = 0;
+        crate::transfer::upload_buffers(
+            &self.core,
+            &[(self.buffer.instance, bytemuck::cast_slice(data))],
+        )?;
+        self.len = data.len();
+        Ok(())
     }
 }
@@ -31,7 +31,7 @@ pub fn request_from_usage_requirements(
 ) -> gpu_alloc::Request {
     gpu_alloc::Request {
         size: requirements.size,
-        align_mask: requirements.alignment,
+        align_mask: requirements.alignment - 1,
         usage,
         memory_types: requirements.memory_type_bits,
     }
@@ -0,0 +1,164 @@
+use crate::transfer::Submission;
+use crate::*;
+use anyhow::{ensure, Result};
+use bytemuck::Pod;
+use std::mem::ManuallyDrop;
+
+impl Vertex {
+    /// Attribute formats for `MaterialDesc::vertex_attributes`; position, normal and uv at
+    /// locations 0, 1 and 2
+    pub fn attributes() -> Vec<vk::Format> {
+        vec![
+            vk::Format::R32G32B32_SFLOAT,
+            vk::Format::R32G32B32_SFLOAT,
+            vk::Format::R32G32_SFLOAT,
+        ]
+    }
+}
+
+impl MeshBundle {
+    /// Create vertex and index buffers sharing a single device-local allocation, and upload
+    /// `vertices` and `indices` into them through a staging buffer. Blocks until the upload has
+    /// completed.
+    pub fn new<V: Pod>(core: &SharedCore, vertices: &[V], indices: &[u32]) -> Result<Self> {
+        let (mesh, upload) = Self::new_streamed(core, vertices, indices)?;
+        upload.finish()?;
+        Ok(mesh)
+    }
+
+    /// Like `new`, but returns without waiting for the upload. The mesh must not be used until
+    /// the returned `Submission` has completed and its buffers have been acquired.
+    pub fn new_streamed<V: Pod>(
+        core: &SharedCore,
+        vertices: &[V],
+        indices: &[u32],
+    ) -> Result<(Self, Submission)> {
+        let vertex_bytes: &[u8] = bytemuck::cast_slice(vertices);
+        let index_bytes: &[u8] = bytemuck::cast_slice(indices);
+        ensure!(
+            !vertex_bytes.is_empty() && !index_bytes.is_empty(),
+            "Meshes must have at least one vertex and one index"
+        );
+
+        let vertex_buffer = create_buffer(
+            core,
+            vertex_bytes.len() as u64,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+        )?;
+        let index_buffer = match create_buffer(
+            core,
+            index_bytes.len() as u64,
+            vk::BufferUsageFlags::INDEX_BUFFER,
+        ) {
+            Ok(index_buffer) => index_buffer,
+            Err(e) => {
+                unsafe { core.device.destroy_buffer(Some(vertex_buffer), None) };
+                return Err(e);
+            }
+        };
+        let destroy_buffers = || unsafe {
+            core.device.destroy_buffer(Some(vertex_buffer), None);
+            core.device.destroy_buffer(Some(index_buffer), None);
+        };
+
+        // Place the index buffer directly after the vertex buffer in the same allocation
+        let (vertex_req, index_req) = unsafe {
+            (
+                core.device
+                    .get_buffer_memory_requirements(vertex_buffer, None),
+                core.device
+                    .get_buffer_memory_requirements(index_buffer, None),
+            )
+        };
+        let index_offset = align_up(vertex_req.size, index_req.alignment);
+        let requirements = vk::MemoryRequirements {
+            size: index_offset + index_req.size,
+            alignment: vertex_req.alignment.max(index_req.alignment),
+            memory_type_bits: vertex_req.memory_type_bits & index_req.memory_type_bits,
+        };
+        let memory = (|| {
+            ensure!(
+                requirements.memory_type_bits != 0,
+                "No memory type is suitable for both vertex and index buffers"
+            );
+            core.allocate(crate::memory::request_from_usage_requirements(
+                requirements,
+                gpu_alloc::UsageFlags::FAST_DEVICE_ACCESS,
+            ))
+        })();
+        let memory = match memory {
+            Ok(memory) => memory,
+            Err(e) => {
+                destroy_buffers();
+                return Err(e);
+            }
+        };
+        let bound = unsafe {
+            core.device
+                .bind_buffer_memory(vertex_buffer, *memory.memory(), memory.offset())
+                .result()
+                .and_then(|_| {
+                    core.device
+                        .bind_buffer_memory(
+                            index_buffer,
+                            *memory.memory(),
+                            memory.offset() + index_offset,
+                        )
+                        .result()
+                })
+        };
+        if let Err(e) = bound {
+            destroy_buffers();
+            let _ = core.deallocate(memory);
+            return Err(e.into());
+        }
+
+        let mesh = Self {
+            vertices: vertex_buffer,
+            indices: index_buffer,
+            n_indices: indices.len() as u32,
+            memory: ManuallyDrop::new(memory),
+            _core: core.clone(),
+        };
+
+        // On failure, the mesh is destroyed by drop
+        let upload = crate::transfer::upload_buffers_async(
+            core,
+            &[(vertex_buffer, vertex_bytes), (index_buffer, index_bytes)],
+        )?;
+        Ok((mesh, upload))
+    }
+
+    /// Name the vertex and index buffers after `name` for debugging
+    pub fn set_name(&self, name: &str) -> Result<()> {
+        self._core
+            .set_name(self.vertices, &format!("{} vertices", name))?;
+        self._core
+            .set_name(self.indices, &format!("{} indices", name))
+    }
+}
+
+impl Drop for MeshBundle {
+    /// Destroy the buffers and free their memory. The GPU must be finished using them.
+    fn drop(&mut self) {
+        unsafe {
+            self._core.device.destroy_buffer(Some(self.vertices), None);
+            self._core.device.destroy_buffer(Some(self.indices), None);
+            let _ = self._core.deallocate(ManuallyDrop::take(&mut self.memory));
+        }
+    }
+}
+
+/// Create an unbound buffer to be written by the utility queue, then transferred to the graphics
+/// queue family
+fn create_buffer(core: &Core, size: u64, usage: vk::BufferUsageFlags) -> Result<vk::Buffer> {
+    let create_info = vk::BufferCreateInfoBuilder::new()
+        .size(size)
+        .usage(usage | vk::BufferUsageFlags::TRANSFER_DST)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE);
+    Ok(unsafe { core.device.create_buffer(&create_info, None, None) }.result()?)
+}
+
+fn align_up(value: u64, alignment: u64) -> u64 {
+    value.div_ceil(alignment) * alignment
+}
@@ -0,0 +1,158 @@
+use crate::Vertex;
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::path::Path;
+
+/// A single object or group from an OBJ file, triangulated and indexed
+#[derive(Clone, Debug, Default)]
+pub struct ObjMesh {
+    pub name: String,
+    /// One vertex per distinct position, normal and uv combination
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+}
+
+/// Load every object and group in a Wavefront OBJ file. Material libraries are ignored.
+pub fn load_obj(path: impl AsRef<Path>) -> Result<Vec<ObjMesh>> {
+    let path = path.as_ref();
+    let file =
+        std::fs::File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    parse_obj(&mut std::io::BufReader::new(file))
+        .with_context(|| format!("Failed to load {}", path.display()))
+}
+
+/// Parse an OBJ file from `reader`; see `load_obj`. Faces are triangulated, and missing normals
+/// or texture coordinates are left zeroed. Objects without faces are skipped.
+pub fn parse_obj(reader: &mut impl BufRead) -> Result<Vec<ObjMesh>> {
+    let options = tobj::LoadOptions {
+        single_index: true,
+        triangulate: true,
+        ignore_points: true,
+        ignore_lines: true,
+    };
+    let no_materials = |_: &Path| Ok((Vec::new(), HashMap::new()));
+    let (models, _) = tobj::load_obj_buf(reader, &options, no_materials)?;
+
+    Ok(models
+        .into_iter()
+        .filter(|model| !model.mesh.indices.is_empty())
+        .map(|model| ObjMesh {
+            name: model.name,
+            vertices: vertices(&model.mesh),
+            indices: model.mesh.indices,
+        })
+        .collect())
+}
+
+/// Interleave the attributes of a singly indexed mesh
+fn vertices(mesh: &tobj::Mesh) -> Vec<Vertex> {
+    (0..mesh.positions.len() / 3)
+        .map(|i| {
+            let mut vertex = Vertex {
+                position: [
+                    mesh.positions[i * 3],
+                    mesh.positions[i * 3 + 1],
+                    mesh.positions[i * 3 + 2],
+                ],
+                ..Default::default()
+            };
+            if let Some(normal) = mesh.normals.get(i * 3..i * 3 + 3) {
+                vertex.normal = [normal[0], normal[1], normal[2]];
+            }
+            // OBJ texture coordinates increase upwards
+            if let Some(uv) = mesh.texcoords.get(i * 2..i * 2 + 2) {
+                vertex.uv = [uv[0], 1. - uv[1]];
+            }
+            vertex
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> Vec<ObjMesh> {
+        parse_obj(&mut source.as_bytes()).unwrap()
+    }
+
+    const QUAD: &str = "
+o quad
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+vt 0 0
+vt 1 0
+vt 1 1
+vt 0 1
+vn 0 0 1
+f 1/1/1 2/2/1 3/3/1
+f 1/1/1 3/3/1 4/4/1
+";
+
+    #[test]
+    fn shared_vertices_are_deduplicated() {
+        let meshes = parse(QUAD);
+        assert_eq!(meshes.len(), 1);
+        assert_eq!(meshes[0].name, "quad");
+        assert_eq!(meshes[0].vertices.len(), 4);
+        assert_eq!(meshes[0].indices.len(), 6);
+    }
+
+    #[test]
+    fn faces_are_triangulated() {
+        let meshes = parse("v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nf 1 2 3 4\n");
+        assert_eq!(meshes[0].vertices.len(), 4);
+        assert_eq!(meshes[0].indices.len(), 6);
+    }
+
+    #[test]
+    fn texture_coordinates_are_flipped() {
+        let meshes = parse(QUAD);
+        let mesh = &meshes[0];
+        for vertex in &mesh.vertices {
+            assert_eq!(vertex.normal, [0., 0., 1.]);
+            // In the file, each texture coordinate equals the position's x and y
+            assert_eq!(vertex.uv, [vertex.position[0], 1. - vertex.position[1]]);
+        }
+    }
+
+    #[test]
+    fn missing_attributes_are_zeroed() {
+        let meshes = parse("v 0 0 0\nv 1 0 0\nv 1 1 0\nf 1 2 3\n");
+        assert_eq!(meshes[0].vertices.len(), 3);
+        for vertex in &meshes[0].vertices {
+            assert_eq!(vertex.normal, [0.; 3]);
+            assert_eq!(vertex.uv, [0.; 2]);
+        }
+        assert_eq!(meshes[0].vertices[1].position, [1., 0., 0.]);
+    }
+
+    #[test]
+    fn objects_and_groups_are_separate_meshes() {
+        let meshes = parse(
+            "
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+o first
+f 1 2 3
+o second
+f 1 3 4
+g third
+f 2 3 4
+o empty
+",
+        );
+        let names = meshes.iter().map(|m| m.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["first", "second", "third"]);
+        for mesh in &meshes {
+            assert_eq!(mesh.vertices.len(), 3);
+            assert_eq!(mesh.indices, [0, 1, 2]);
+        }
+        assert_eq!(meshes[1].vertices[2].position, [0., 1., 0.]);
+    }
+}
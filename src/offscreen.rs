@@ -0,0 +1,288 @@
+use crate::default_engine::{
+    begin_render_pass, create_depth_image, create_framebuffer, create_render_pass,
+};
+use crate::mem_objects::MemObject;
+use crate::*;
+use anyhow::{ensure, Result};
+use gpu_alloc_erupt::EruptMemoryDevice as EMD;
+
+/// Color format of offscreen targets; read back as RGBA8
+pub const COLOR_FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
+
+/// A single color + depth render target, with a host-visible buffer the color image is copied
+/// into at the end of each frame.
+pub struct OffscreenTarget {
+    pub color: Image,
+    pub depth: Image,
+    pub framebuffer: vk::Framebuffer,
+    pub readback: MemObject<vk::Buffer>,
+    /// Signalled when the frame rendered into this target has been copied into `readback`
+    pub fence: vk::Fence,
+    _core: SharedCore,
+}
+
+/// Engine which renders into a ring of offscreen targets instead of a swapchain. Each frame's
+/// color output is copied into host memory, where it can be read back as RGBA8 bytes.
+pub struct OffscreenEngine {
+    pub targets: Vec<OffscreenTarget>,
+    pub command_buffers: Vec<vk::CommandBuffer>,
+    pub command_pool: vk::CommandPool,
+    pub render_pass: vk::RenderPass,
+    pub extent: vk::Extent2D,
+    pub clear_color: [f32; 4],
+    /// The index of the target that is currently writeable
+    pub frame_idx: usize,
+    pub core: SharedCore,
+}
+
+impl OffscreenEngine {
+    /// Create an engine rendering at `extent` into `n_targets` targets, so that up to `n_targets`
+    /// frames may be in flight at once.
+    pub fn new(
+        core: SharedCore,
+        hardware: &HardwareSelection,
+        extent: vk::Extent2D,
+        n_targets: usize,
+    ) -> Result<Self> {
+        ensure!(n_targets > 0, "At least one offscreen target is required");
+
+        let render_pass =
+            create_render_pass(&core, COLOR_FORMAT, vk::ImageLayout::TRANSFER_SRC_OPTIMAL)?;
+        core.set_name(render_pass, "Offscreen pass")?;
+
+        let create_info = vk::CommandPoolCreateInfoBuilder::new()
+            .queue_family_index(hardware.graphics_queue_family)
+            .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER);
+        let command_pool =
+            unsafe { core.device.create_command_pool(&create_info, None, None) }.result()?;
+
+        let allocate_info = vk::CommandBufferAllocateInfoBuilder::new()
+            .command_pool(command_pool)
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_buffer_count(n_targets as u32);
+        let command_buffers =
+            unsafe { core.device.allocate_command_buffers(&allocate_info) }.result()?;
+
+        let targets = (0..n_targets)
+            .map(|i| {
+                let target = OffscreenTarget::new(&core, render_pass, extent)?;
+                let name = format!("Offscreen target {}", i);
+                target.color.set_name(&format!("{} color", name))?;
+                target.depth.set_name(&format!("{} depth", name))?;
+                core.set_name(target.readback.instance, &format!("{} readback", name))?;
+                core.set_name(target.fence, &format!("{} fence", name))?;
+                core.set_name(command_buffers[i], &format!("{} commands", name))?;
+                Ok(target)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            targets,
+            command_buffers,
+            command_pool,
+            render_pass,
+            extent,
+            clear_color: [0., 0., 0., 1.],
+            frame_idx: 0,
+            core,
+        })
+    }
+
+    /// Wait for the current target to become available, then begin recording into it. The
+    /// returned command buffer is inside the render pass, with viewport and scissor set.
+    pub fn begin_frame(&mut self) -> Result<vk::CommandBuffer> {
+        let target = &self.targets[self.frame_idx];
+        let command_buffer = self.command_buffers[self.frame_idx];
+        unsafe {
+            self.core
+                .device
+                .wait_for_fences(&[target.fence], true, u64::MAX)
+                .result()?;
+            self.core
+                .device
+                .reset_command_buffer(command_buffer, None)
+                .result()?;
+            let begin_info = vk::CommandBufferBeginInfoBuilder::new()
+                .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+            self.core
+                .device
+                .begin_command_buffer(command_buffer, &begin_info)
+                .result()?;
+        }
+
+        begin_render_pass(
+            &self.core,
+            command_buffer,
+            self.render_pass,
+            target.framebuffer,
+            self.extent,
+            self.clear_color,
+        );
+
+        Ok(command_buffer)
+    }
+
+    /// Finish the frame begun with `begin_frame`, and submit it along with a copy of the color
+    /// image into host memory. Returns the index of the target to pass to `read_target`.
+    pub fn end_frame(&mut self) -> Result<usize> {
+        let target = &self.targets[self.frame_idx];
+        let command_buffer = self.command_buffers[self.frame_idx];
+        let device = &self.core.device;
+        unsafe {
+            device.cmd_end_render_pass(command_buffer);
+
+            let region = vk::BufferImageCopyBuilder::new()
+                .buffer_offset(0)
+                .buffer_row_length(0)
+                .buffer_image_height(0)
+                .image_subresource(
+                    vk::ImageSubresourceLayersBuilder::new()
+                        .aspect_mask(vk::ImageAspectFlags::COLOR)
+                        .mip_level(0)
+                        .base_array_layer(0)
+                        .layer_count(1)
+                        .build(),
+                )
+                .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+                .image_extent(vk::Extent3D {
+                    width: self.extent.width,
+                    height: self.extent.height,
+                    depth: 1,
+                });
+            device.cmd_copy_image_to_buffer(
+                command_buffer,
+                target.color.image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                target.readback.instance,
+                &[region],
+            );
+
+            let barrier = vk::BufferMemoryBarrierBuilder::new()
+                .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .dst_access_mask(vk::AccessFlags::HOST_READ)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .buffer(target.readback.instance)
+                .offset(0)
+                .size(vk::WHOLE_SIZE);
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::HOST,
+                None,
+                &[],
+                &[barrier],
+                &[],
+            );
+
+            device.end_command_buffer(command_buffer).result()?;
+
+            let command_buffers = [command_buffer];
+            let submit_info = vk::SubmitInfoBuilder::new().command_buffers(&command_buffers);
+            device.reset_fences(&[target.fence]).result()?;
+            device
+                .queue_submit(self.core.graphics_queue, &[submit_info], Some(target.fence))
+                .result()?;
+        }
+
+        let submitted = self.frame_idx;
+        self.frame_idx = (self.frame_idx + 1) % self.targets.len();
+        self.core.check_validation()?;
+        Ok(submitted)
+    }
+
+    /// Wait for the frame last submitted to target `idx` to finish, and return its color output
+    /// as tightly packed RGBA8 rows, top to bottom.
+    pub fn read_target(&mut self, idx: usize) -> Result<Vec<u8>> {
+        let target = &mut self.targets[idx];
+        let mut pixels = vec![0; rgba8_size(self.extent) as usize];
+        unsafe {
+            self.core
+                .device
+                .wait_for_fences(&[target.fence], true, u64::MAX)
+                .result()?;
+            target.readback.memory_mut().read_bytes(
+                EMD::wrap(&self.core.device),
+                0,
+                &mut pixels,
+            )?;
+        }
+        Ok(pixels)
+    }
+
+    /// Render a single frame with `record` and wait for its pixels
+    pub fn render(&mut self, record: impl FnOnce(vk::CommandBuffer)) -> Result<Vec<u8>> {
+        let command_buffer = self.begin_frame()?;
+        record(command_buffer);
+        let idx = self.end_frame()?;
+        self.read_target(idx)
+    }
+}
+
+impl OffscreenTarget {
+    fn new(core: &SharedCore, render_pass: vk::RenderPass, extent: vk::Extent2D) -> Result<Self> {
+        let color = Image::new(
+            core,
+            extent,
+            COLOR_FORMAT,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+            vk::ImageAspectFlags::COLOR,
+        )?;
+        let depth = create_depth_image(core, extent)?;
+        let framebuffer = create_framebuffer(core, render_pass, color.view, depth.view, extent)?;
+
+        let create_info = vk::BufferCreateInfoBuilder::new()
+            .size(rgba8_size(extent))
+            .usage(vk::BufferUsageFlags::TRANSFER_DST)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        let readback = MemObject::<vk::Buffer>::new(
+            core,
+            create_info,
+            gpu_alloc::UsageFlags::DOWNLOAD | gpu_alloc::UsageFlags::HOST_ACCESS,
+        )?;
+
+        let create_info = vk::FenceCreateInfoBuilder::new().flags(vk::FenceCreateFlags::SIGNALED);
+        let fence = unsafe { core.device.create_fence(&create_info, None, None) }.result()?;
+
+        Ok(Self {
+            color,
+            depth,
+            framebuffer,
+            readback,
+            fence,
+            _core: core.clone(),
+        })
+    }
+}
+
+impl Drop for OffscreenTarget {
+    fn drop(&mut self) {
+        unsafe {
+            self._core.device.destroy_fence(Some(self.fence), None);
+            self._core
+                .device
+                .destroy_framebuffer(Some(self.framebuffer), None);
+        }
+    }
+}
+
+impl Drop for OffscreenEngine {
+    fn drop(&mut self) {
+        let core = self.core.clone();
+        unsafe {
+            let _ = core.device.device_wait_idle();
+            self.targets.clear();
+            core.device
+                .free_command_buffers(self.command_pool, &self.command_buffers);
+            core.device
+                .destroy_command_pool(Some(self.command_pool), None);
+            core.device
+                .destroy_render_pass(Some(self.render_pass), None);
+        }
+    }
+}
+
+/// Size in bytes of a tightly packed RGBA8 image of the given extent
+fn rgba8_size(extent: vk::Extent2D) -> u64 {
+    extent.width as u64 * extent.height as u64 * 4
+}
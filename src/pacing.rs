@@ -0,0 +1,178 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Number of recent frames `FrameStats` are computed over
+const STATS_WINDOW: usize = 120;
+
+/// Frame time statistics over recent frames. Frame time is measured from the start of one frame
+/// to the start of the next, including any time spent in the frame limiter.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FrameStats {
+    /// Duration of the most recent frame
+    pub frame_time: Duration,
+    pub average: Duration,
+    pub min: Duration,
+    pub max: Duration,
+    /// Total frames measured
+    pub frames: u64,
+}
+
+impl FrameStats {
+    /// Frames per second, from the average frame time
+    pub fn fps(&self) -> f32 {
+        if self.average.as_secs_f32() > 0. {
+            1. / self.average.as_secs_f32()
+        } else {
+            0.
+        }
+    }
+}
+
+/// Optionally limits the frame rate by sleeping on the CPU, and measures frame times
+#[derive(Clone, Debug, Default)]
+pub struct FramePacer {
+    interval: Option<Duration>,
+    next_due: Option<Instant>,
+    last_frame: Option<Instant>,
+    recent: VecDeque<Duration>,
+    stats: FrameStats,
+}
+
+impl FramePacer {
+    /// Limit the frame rate to `fps`, or remove the limit with `None`. Rates which are not
+    /// positive, or too small to represent as an interval, also remove the limit.
+    pub fn set_target_fps(&mut self, fps: Option<f32>) {
+        self.interval = fps
+            .filter(|&fps| fps > 0.)
+            .and_then(|fps| Duration::try_from_secs_f32(1. / fps).ok());
+        self.next_due = None;
+    }
+
+    pub fn target_fps(&self) -> Option<f32> {
+        self.interval.map(|interval| 1. / interval.as_secs_f32())
+    }
+
+    pub fn stats(&self) -> FrameStats {
+        self.stats
+    }
+
+    /// Sleep until the next frame is due, then record the time since the previous frame
+    pub fn begin_frame(&mut self) {
+        if let Some(interval) = self.interval {
+            if let Some(due) = self.next_due {
+                let now = Instant::now();
+                if due > now {
+                    std::thread::sleep(due - now);
+                }
+            }
+            self.next_due = Some(next_due(self.next_due, Instant::now(), interval));
+        }
+
+        let now = Instant::now();
+        if let Some(last) = self.last_frame.replace(now) {
+            self.record(now - last);
+        }
+    }
+
+    fn record(&mut self, frame_time: Duration) {
+        if self.recent.len() == STATS_WINDOW {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(frame_time);
+
+        self.stats = FrameStats {
+            frame_time,
+            average: self.recent.iter().sum::<Duration>() / self.recent.len() as u32,
+            min: self.recent.iter().copied().min().unwrap_or_default(),
+            max: self.recent.iter().copied().max().unwrap_or_default(),
+            frames: self.stats.frames + 1,
+        };
+    }
+}
+
+/// When the frame after one beginning at `now` is due, given when this frame was due. Keeps a
+/// steady cadence, without bursting to catch up after a late frame.
+fn next_due(due: Option<Instant>, now: Instant, interval: Duration) -> Instant {
+    match due {
+        Some(due) if now < due + interval => due + interval,
+        _ => now + interval,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn stats_cover_recent_frames() {
+        let mut pacer = FramePacer::default();
+        for &frame_time in &[10, 30, 20] {
+            pacer.record(ms(frame_time));
+        }
+        let stats = pacer.stats();
+        assert_eq!(stats.frame_time, ms(20));
+        assert_eq!(stats.average, ms(20));
+        assert_eq!(stats.min, ms(10));
+        assert_eq!(stats.max, ms(30));
+        assert_eq!(stats.frames, 3);
+        assert_eq!(stats.fps(), 50.);
+    }
+
+    #[test]
+    fn stats_window_drops_old_frames() {
+        let mut pacer = FramePacer::default();
+        pacer.record(ms(100));
+        for _ in 0..STATS_WINDOW {
+            pacer.record(ms(5));
+        }
+        let stats = pacer.stats();
+        assert_eq!(stats.max, ms(5));
+        assert_eq!(stats.average, ms(5));
+        assert_eq!(stats.frames, STATS_WINDOW as u64 + 1);
+    }
+
+    #[test]
+    fn target_fps_limits() {
+        let mut pacer = FramePacer::default();
+        pacer.set_target_fps(Some(50.));
+        assert_eq!(pacer.interval, Some(ms(20)));
+        for &fps in &[0., -1., f32::NAN, f32::MIN_POSITIVE] {
+            pacer.set_target_fps(Some(fps));
+            assert_eq!(pacer.target_fps(), None, "{}", fps);
+        }
+        pacer.set_target_fps(Some(50.));
+        pacer.set_target_fps(None);
+        assert_eq!(pacer.target_fps(), None);
+    }
+
+    #[test]
+    fn cadence_is_steady() {
+        let start = Instant::now();
+        let interval = ms(10);
+        assert_eq!(next_due(None, start, interval), start + ms(10));
+        // Early and slightly late frames keep to the schedule
+        assert_eq!(
+            next_due(Some(start + ms(10)), start + ms(10), interval),
+            start + ms(20)
+        );
+        assert_eq!(
+            next_due(Some(start + ms(10)), start + ms(15), interval),
+            start + ms(20)
+        );
+    }
+
+    #[test]
+    fn no_burst_after_late_frame() {
+        let start = Instant::now();
+        let interval = ms(10);
+        // A frame beginning a whole interval late restarts the schedule from now
+        assert_eq!(
+            next_due(Some(start + ms(10)), start + ms(45), interval),
+            start + ms(55)
+        );
+    }
+}
@@ -0,0 +1,535 @@
+use crate::*;
+use anyhow::{ensure, format_err, Result};
+use gltf::image::Format;
+use std::path::Path;
+
+/// Material parameters of a glTF metallic-roughness material. Matches the std140 block
+/// `uniform Pbr { vec4 base_color; vec3 emissive; float metallic; float roughness;
+/// float normal_scale; float occlusion_strength; float alpha_cutoff; }`. These are not uploaded
+/// by `Engine::add_scene`, whose materials only bind a texture; shaders needing them may upload
+/// them with a `TypedBuffer`, or bake them into push constants or specialization constants.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PbrParams {
+    pub base_color_factor: [f32; 4],
+    pub emissive_factor: [f32; 3],
+    pub metallic_factor: f32,
+    pub roughness_factor: f32,
+    pub normal_scale: f32,
+    pub occlusion_strength: f32,
+    /// Fragments with a lower alpha are discarded; zero unless the material is alpha masked
+    pub alpha_cutoff: f32,
+}
+
+unsafe impl bytemuck::Zeroable for PbrParams {}
+unsafe impl bytemuck::Pod for PbrParams {}
+
+/// A material in a `Scene`. Textures are indices into `Scene::textures`.
+#[derive(Clone, Debug)]
+pub struct SceneMaterial {
+    pub name: Option<String>,
+    pub params: PbrParams,
+    pub base_color_texture: Option<usize>,
+    pub metallic_roughness_texture: Option<usize>,
+    pub normal_texture: Option<usize>,
+    pub occlusion_texture: Option<usize>,
+    pub emissive_texture: Option<usize>,
+    pub blend: bool,
+    pub double_sided: bool,
+}
+
+/// A texture in a `Scene`, decoded into tightly packed RGBA8 rows, top to bottom
+#[derive(Clone, Debug)]
+pub struct SceneTexture {
+    pub name: Option<String>,
+    pub extent: vk::Extent2D,
+    pub pixels: Vec<u8>,
+    /// Whether the texture holds color data, and should be sampled as sRGB
+    pub srgb: bool,
+}
+
+/// Part of a `SceneMesh` drawn with a single material
+#[derive(Clone, Debug)]
+pub struct ScenePrimitive {
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+    /// Index into `Scene::materials`, or `None` for the default material
+    pub material: Option<usize>,
+}
+
+#[derive(Clone, Debug)]
+pub struct SceneMesh {
+    pub name: Option<String>,
+    pub primitives: Vec<ScenePrimitive>,
+}
+
+/// A node in the scene tree. `mesh` and `children` are indices into `Scene::meshes` and
+/// `Scene::nodes`.
+#[derive(Clone, Debug)]
+pub struct SceneNode {
+    pub name: Option<String>,
+    /// Column-major transform relative to the parent node
+    pub transform: [f32; 16],
+    pub mesh: Option<usize>,
+    pub children: Vec<usize>,
+}
+
+/// The contents of a glTF 2.0 file, ready to upload with `Engine::add_scene`
+#[derive(Clone, Debug)]
+pub struct Scene {
+    pub meshes: Vec<SceneMesh>,
+    pub materials: Vec<SceneMaterial>,
+    pub textures: Vec<SceneTexture>,
+    pub nodes: Vec<SceneNode>,
+    /// Root nodes of the file's default scene, or of the first scene if there is no default
+    pub roots: Vec<usize>,
+}
+
+/// Engine resources created from a `Scene`; see `Engine::add_scene`
+#[derive(Clone, Debug)]
+pub struct SceneInstance {
+    /// For each scene mesh, the engine mesh and scene material of each primitive
+    pub meshes: Vec<Vec<(MeshKey, Option<usize>)>>,
+    /// The engine texture for each scene texture
+    pub textures: Vec<TextureKey>,
+}
+
+impl Scene {
+    /// Import a .gltf or .glb file, along with any buffers and images it refers to
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let (document, buffers, images) = gltf::import(path)
+            .map_err(|e| format_err!("Failed to import {}: {}", path.display(), e))?;
+        Self::from_gltf(&document, &buffers, &images)
+    }
+
+    /// Import a .glb file held in memory. Buffers and images must be stored in the binary chunk,
+    /// as there is no path to resolve other references against.
+    pub fn from_slice(bytes: &[u8]) -> Result<Self> {
+        let (document, buffers, images) = gltf::import_slice(bytes)?;
+        Self::from_gltf(&document, &buffers, &images)
+    }
+
+    fn from_gltf(
+        document: &gltf::Document,
+        buffers: &[gltf::buffer::Data],
+        images: &[gltf::image::Data],
+    ) -> Result<Self> {
+        let materials: Vec<SceneMaterial> = document.materials().map(material).collect();
+
+        // Color textures are sRGB encoded, all others are linear
+        let mut srgb = vec![false; document.textures().len()];
+        for material in &materials {
+            for &texture in material
+                .base_color_texture
+                .iter()
+                .chain(&material.emissive_texture)
+            {
+                srgb[texture] = true;
+            }
+        }
+
+        let textures = document
+            .textures()
+            .map(|texture| {
+                let image = &images[texture.source().index()];
+                Ok(SceneTexture {
+                    name: texture.name().map(str::to_owned),
+                    extent: vk::Extent2D {
+                        width: image.width,
+                        height: image.height,
+                    },
+                    pixels: rgba8(image)?,
+                    srgb: srgb[texture.index()],
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        let meshes = document
+            .meshes()
+            .map(|mesh| mesh_data(&mesh, buffers))
+            .collect::<Result<_>>()?;
+
+        let nodes: Vec<SceneNode> = document
+            .nodes()
+            .map(|node| SceneNode {
+                name: node.name().map(str::to_owned),
+                transform: flatten(node.transform().matrix()),
+                mesh: node.mesh().map(|mesh| mesh.index()),
+                children: node.children().map(|child| child.index()).collect(),
+            })
+            .collect();
+
+        // Each node may only have a single parent, which rules out cycles below the roots
+        let mut parents = vec![0; nodes.len()];
+        for &child in nodes.iter().flat_map(|node| &node.children) {
+            parents[child] += 1;
+            ensure!(
+                parents[child] == 1,
+                "Node {} has more than one parent",
+                child
+            );
+        }
+
+        let scene = document
+            .default_scene()
+            .or_else(|| document.scenes().next());
+        let roots: Vec<usize> = scene
+            .map(|scene| scene.nodes().map(|node| node.index()).collect())
+            .unwrap_or_default();
+        for &root in &roots {
+            ensure!(parents[root] == 0, "Root node {} has a parent", root);
+        }
+
+        Ok(Self {
+            meshes,
+            materials,
+            textures,
+            nodes,
+            roots,
+        })
+    }
+
+    /// Draw commands for every primitive reachable from `roots`, with `transform` applied above
+    /// the roots. `material` chooses the engine material for each scene material.
+    pub fn draws(
+        &self,
+        instance: &SceneInstance,
+        transform: [f32; 16],
+        mut material: impl FnMut(Option<usize>) -> DefaultKey,
+    ) -> Vec<DrawCmd> {
+        let mut draws = Vec::new();
+        let mut stack: Vec<(usize, [f32; 16])> =
+            self.roots.iter().map(|&root| (root, transform)).collect();
+        while let Some((idx, parent)) = stack.pop() {
+            let node = &self.nodes[idx];
+            let transform = mul(&parent, &node.transform);
+            if let Some(primitives) = node.mesh.and_then(|mesh| instance.meshes.get(mesh)) {
+                for &(mesh, scene_material) in primitives {
+                    draws.push(DrawCmd {
+                        material: material(scene_material),
+                        mesh,
+                        transform,
+                    });
+                }
+            }
+            stack.extend(node.children.iter().map(|&child| (child, transform)));
+        }
+        draws
+    }
+}
+
+impl SceneMaterial {
+    /// A `MaterialDesc` for drawing primitives with this material, using the `Vertex` layout
+    pub fn desc(&self) -> MaterialDesc {
+        MaterialDesc {
+            cull_mode: if self.double_sided {
+                vk::CullModeFlags::NONE
+            } else {
+                vk::CullModeFlags::BACK
+            },
+            blend: self.blend,
+            depth_write: !self.blend,
+            vertex_attributes: Vertex::attributes(),
+            ..Default::default()
+        }
+    }
+}
+
+fn material(material: gltf::Material) -> SceneMaterial {
+    let pbr = material.pbr_metallic_roughness();
+    let alpha_cutoff = match material.alpha_mode() {
+        gltf::material::AlphaMode::Mask => material.alpha_cutoff(),
+        _ => 0.,
+    };
+    let normal = material.normal_texture();
+    let occlusion = material.occlusion_texture();
+    SceneMaterial {
+        name: material.name().map(str::to_owned),
+        params: PbrParams {
+            base_color_factor: pbr.base_color_factor(),
+            emissive_factor: material.emissive_factor(),
+            metallic_factor: pbr.metallic_factor(),
+            roughness_factor: pbr.roughness_factor(),
+            normal_scale: normal.as_ref().map_or(1., |info| info.scale()),
+            occlusion_strength: occlusion.as_ref().map_or(1., |info| info.strength()),
+            alpha_cutoff,
+        },
+        base_color_texture: pbr.base_color_texture().map(|info| info.texture().index()),
+        metallic_roughness_texture: pbr
+            .metallic_roughness_texture()
+            .map(|info| info.texture().index()),
+        normal_texture: normal.map(|info| info.texture().index()),
+        occlusion_texture: occlusion.map(|info| info.texture().index()),
+        emissive_texture: material
+            .emissive_texture()
+            .map(|info| info.texture().index()),
+        blend: material.alpha_mode() == gltf::material::AlphaMode::Blend,
+        double_sided: material.double_sided(),
+    }
+}
+
+/// Read the triangle list primitives of `mesh`. Missing normals or texture coordinates are left
+/// zeroed, and unindexed primitives are given sequential indices.
+fn mesh_data(mesh: &gltf::Mesh, buffers: &[gltf::buffer::Data]) -> Result<SceneMesh> {
+    let mut primitives = Vec::new();
+    for primitive in mesh.primitives() {
+        if primitive.mode() != gltf::mesh::Mode::Triangles {
+            log::warn!(
+                "Skipping {:?} primitive of mesh {}; only triangle lists are supported",
+                primitive.mode(),
+                mesh.index()
+            );
+            continue;
+        }
+
+        let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(|data| &data.0[..]));
+        let mut vertices: Vec<Vertex> = reader
+            .read_positions()
+            .ok_or_else(|| format_err!("Mesh {} has a primitive without positions", mesh.index()))?
+            .map(|position| Vertex {
+                position,
+                ..Default::default()
+            })
+            .collect();
+        if let Some(normals) = reader.read_normals() {
+            for (vertex, normal) in vertices.iter_mut().zip(normals) {
+                vertex.normal = normal;
+            }
+        }
+        if let Some(uvs) = reader.read_tex_coords(0) {
+            for (vertex, uv) in vertices.iter_mut().zip(uvs.into_f32()) {
+                vertex.uv = uv;
+            }
+        }
+
+        let indices: Vec<u32> = match reader.read_indices() {
+            Some(indices) => indices.into_u32().collect(),
+            None => (0..vertices.len() as u32).collect(),
+        };
+        ensure!(
+            indices.iter().all(|&i| (i as usize) < vertices.len()),
+            "Mesh {} has an index out of bounds",
+            mesh.index()
+        );
+        if indices.is_empty() {
+            continue;
+        }
+
+        primitives.push(ScenePrimitive {
+            vertices,
+            indices,
+            material: primitive.material().index(),
+        });
+    }
+
+    Ok(SceneMesh {
+        name: mesh.name().map(str::to_owned),
+        primitives,
+    })
+}
+
+/// Convert decoded image data into RGBA8. 16 bit channels are truncated to their high byte.
+fn rgba8(image: &gltf::image::Data) -> Result<Vec<u8>> {
+    let (channels, bytes_per_channel, bgr) = match image.format {
+        Format::R8 => (1, 1, false),
+        Format::R8G8 => (2, 1, false),
+        Format::R8G8B8 => (3, 1, false),
+        Format::R8G8B8A8 => (4, 1, false),
+        Format::B8G8R8 => (3, 1, true),
+        Format::B8G8R8A8 => (4, 1, true),
+        Format::R16 => (1, 2, false),
+        Format::R16G16 => (2, 2, false),
+        Format::R16G16B16 => (3, 2, false),
+        Format::R16G16B16A16 => (4, 2, false),
+    };
+    let texel_size = channels * bytes_per_channel;
+    let n_texels = image.width as usize * image.height as usize;
+    ensure!(
+        image.pixels.len() == n_texels * texel_size,
+        "Image data does not match its size"
+    );
+
+    let mut pixels = Vec::with_capacity(n_texels * 4);
+    for texel in image.pixels.chunks_exact(texel_size) {
+        // Little endian, so the high byte is last
+        let channel = |i: usize| texel[i * bytes_per_channel + bytes_per_channel - 1];
+        let rgba = match channels {
+            // Greyscale, with alpha if there are two channels
+            1 => [channel(0), channel(0), channel(0), 255],
+            2 => [channel(0), channel(0), channel(0), channel(1)],
+            3 if bgr => [channel(2), channel(1), channel(0), 255],
+            3 => [channel(0), channel(1), channel(2), 255],
+            _ if bgr => [channel(2), channel(1), channel(0), channel(3)],
+            _ => [channel(0), channel(1), channel(2), channel(3)],
+        };
+        pixels.extend_from_slice(&rgba);
+    }
+    Ok(pixels)
+}
+
+fn flatten(matrix: [[f32; 4]; 4]) -> [f32; 16] {
+    let mut flat = [0.; 16];
+    for (column, values) in matrix.iter().enumerate() {
+        flat[column * 4..column * 4 + 4].copy_from_slice(values);
+    }
+    flat
+}
+
+/// Product of two column-major matrices
+fn mul(a: &[f32; 16], b: &[f32; 16]) -> [f32; 16] {
+    let mut out = [0.; 16];
+    for column in 0..4 {
+        for row in 0..4 {
+            out[column * 4 + row] = (0..4).map(|k| a[k * 4 + row] * b[column * 4 + k]).sum();
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A textured unit cube below a root node. The cube mesh also has a line list primitive.
+    const CUBE_GLB: &[u8] = include_bytes!("test_data/textured_cube.glb");
+
+    fn gltf_json(nodes: &str, roots: &str) -> String {
+        format!(
+            r#"{{"asset":{{"version":"2.0"}},"scenes":[{{"nodes":{}}}],"nodes":{}}}"#,
+            roots, nodes
+        )
+    }
+
+    #[test]
+    fn glb_is_imported() -> Result<()> {
+        let scene = Scene::from_slice(CUBE_GLB)?;
+        assert_eq!(scene.meshes.len(), 1);
+        assert_eq!(scene.meshes[0].name.as_deref(), Some("cube"));
+        // The line list is skipped
+        let primitives = &scene.meshes[0].primitives;
+        assert_eq!(primitives.len(), 1);
+        assert_eq!(primitives[0].vertices.len(), 24);
+        assert_eq!(primitives[0].indices.len(), 36);
+        assert_eq!(primitives[0].material, Some(0));
+        assert!(primitives[0]
+            .vertices
+            .iter()
+            .all(|v| v.normal.iter().map(|n| n * n).sum::<f32>() == 1.));
+
+        assert_eq!(scene.materials.len(), 1);
+        let material = &scene.materials[0];
+        assert_eq!(material.base_color_texture, Some(0));
+        assert_eq!(material.params.metallic_factor, 0.);
+        assert_eq!(material.params.roughness_factor, 0.5);
+        assert_eq!(material.params.base_color_factor, [1.; 4]);
+
+        // An RGB PNG, expanded to RGBA and sampled as sRGB as it holds base color
+        assert_eq!(scene.textures.len(), 1);
+        let texture = &scene.textures[0];
+        assert_eq!((texture.extent.width, texture.extent.height), (2, 2));
+        assert!(texture.srgb);
+        assert_eq!(
+            texture.pixels,
+            [255, 0, 0, 255, 0, 255, 0, 255, 0, 0, 255, 255, 255, 255, 255, 255]
+        );
+
+        assert_eq!(scene.roots, [0]);
+        assert_eq!(scene.nodes[0].children, [1]);
+        assert_eq!(scene.nodes[1].mesh, Some(0));
+        Ok(())
+    }
+
+    #[test]
+    fn node_transforms_are_flattened() -> Result<()> {
+        let scene = Scene::from_slice(CUBE_GLB)?;
+        let instance = SceneInstance {
+            meshes: vec![vec![(MeshKey::default(), Some(0))]],
+            textures: vec![],
+        };
+        let mut identity = [0.; 16];
+        for i in 0..4 {
+            identity[i * 5] = 1.;
+        }
+        let draws = scene.draws(&instance, identity, |_| DefaultKey::default());
+        assert_eq!(draws.len(), 1);
+        // Translated by (1, 0, 0) at the root, and by (0, 2, 0) and scaled by 2 below it
+        #[rustfmt::skip]
+        let expected = [
+            2., 0., 0., 0.,
+            0., 2., 0., 0.,
+            0., 0., 2., 0.,
+            1., 2., 0., 1.,
+        ];
+        assert_eq!(draws[0].transform, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn multiple_parents_are_rejected() {
+        let json = gltf_json(r#"[{"children":[2]},{"children":[2]},{}]"#, "[0,1]");
+        let err = Scene::from_slice(json.as_bytes()).unwrap_err();
+        assert!(err.to_string().contains("more than one parent"), "{}", err);
+    }
+
+    #[test]
+    fn cycles_are_rejected() {
+        let json = gltf_json(r#"[{"children":[1]},{"children":[0]}]"#, "[0]");
+        let err = Scene::from_slice(json.as_bytes()).unwrap_err();
+        assert!(err.to_string().contains("has a parent"), "{}", err);
+    }
+
+    fn image(format: Format, pixels: Vec<u8>) -> gltf::image::Data {
+        gltf::image::Data {
+            pixels,
+            format,
+            width: 1,
+            height: 1,
+        }
+    }
+
+    #[test]
+    fn rgb_is_expanded_to_rgba() -> Result<()> {
+        assert_eq!(
+            rgba8(&image(Format::R8G8B8, vec![1, 2, 3]))?,
+            [1, 2, 3, 255]
+        );
+        assert_eq!(
+            rgba8(&image(Format::B8G8R8, vec![1, 2, 3]))?,
+            [3, 2, 1, 255]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn greyscale_is_expanded_to_rgba() -> Result<()> {
+        assert_eq!(rgba8(&image(Format::R8, vec![7]))?, [7, 7, 7, 255]);
+        assert_eq!(rgba8(&image(Format::R8G8, vec![7, 9]))?, [7, 7, 7, 9]);
+        assert_eq!(rgba8(&image(Format::R16, vec![0, 7]))?, [7, 7, 7, 255]);
+        assert_eq!(
+            rgba8(&image(Format::R16G16, vec![0, 7, 0, 9]))?,
+            [7, 7, 7, 9]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn sixteen_bit_channels_keep_high_byte() -> Result<()> {
+        let pixels = vec![0x01, 0x10, 0x02, 0x20, 0x03, 0x30, 0x04, 0x40];
+        let rgba = rgba8(&image(Format::R16G16B16A16, pixels))?;
+        assert_eq!(rgba, [0x10, 0x20, 0x30, 0x40]);
+        let rgba = rgba8(&image(Format::R16G16B16, vec![0, 1, 0, 2, 0, 3]))?;
+        assert_eq!(rgba, [1, 2, 3, 255]);
+        Ok(())
+    }
+
+    #[test]
+    fn image_size_mismatch_is_rejected() {
+        assert!(rgba8(&image(Format::R8G8B8A8, vec![1, 2, 3])).is_err());
+    }
+
+    #[test]
+    fn pbr_params_match_std140_block() {
+        assert_eq!(std::mem::size_of::<PbrParams>(), 48);
+    }
+}
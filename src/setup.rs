@@ -0,0 +1,179 @@
+use crate::core::{
+    available_instance_extensions, available_instance_layers, check_instance_support, contains_name,
+};
+use crate::windowed::hardware::{union_features, DeviceSelector, SurfacePreferences};
+use crate::*;
+use anyhow::Result;
+use erupt::{
+    extensions::ext_debug_utils::EXT_DEBUG_UTILS_EXTENSION_NAME,
+    utils::loading::DefaultEntryLoader, EntryLoader,
+};
+use std::ffi::CStr;
+
+const LAYER_KHRONOS_VALIDATION: *const i8 = erupt::cstr!("VK_LAYER_KHRONOS_validation");
+
+impl VulkanSetup {
+    /// Begin building a setup; see `VulkanSetupBuilder`
+    pub fn builder() -> VulkanSetupBuilder {
+        VulkanSetupBuilder::default()
+    }
+}
+
+/// Builds a `VulkanSetup`, checking that the requested layers and instance extensions are
+/// available before any instance is created
+#[derive(Clone, Debug)]
+pub struct VulkanSetupBuilder {
+    validation: bool,
+    panic_on_validation_error: bool,
+    api_version: u32,
+    instance_layers: Vec<*const i8>,
+    instance_extensions: Vec<*const i8>,
+    device_extensions: Vec<*const i8>,
+    optional_device_extensions: Vec<*const i8>,
+    required_features: vk::PhysicalDeviceFeatures,
+    optional_features: vk::PhysicalDeviceFeatures,
+    device_selector: DeviceSelector,
+    surface_preferences: SurfacePreferences,
+}
+
+impl Default for VulkanSetupBuilder {
+    /// Defaults to Vulkan 1.0.0 without validation
+    fn default() -> Self {
+        Self {
+            validation: false,
+            panic_on_validation_error: false,
+            api_version: vk::make_version(1, 0, 0),
+            instance_layers: Vec::new(),
+            instance_extensions: Vec::new(),
+            device_extensions: Vec::new(),
+            optional_device_extensions: Vec::new(),
+            required_features: Default::default(),
+            optional_features: Default::default(),
+            device_selector: DeviceSelector::default(),
+            surface_preferences: SurfacePreferences::default(),
+        }
+    }
+}
+
+impl VulkanSetupBuilder {
+    /// Request the Khronos validation layer and debug utils extension. If the layer is not
+    /// installed, a warning is logged and the setup is built without it.
+    pub fn validation(mut self, validation: bool) -> Self {
+        self.validation = validation;
+        self
+    }
+
+    /// Fail on validation errors, for use in test suites: they are returned as errors from the
+    /// next queue submission, or panic when the core is dropped. Has no effect without validation.
+    pub fn panic_on_validation_error(mut self, panic: bool) -> Self {
+        self.panic_on_validation_error = panic;
+        self
+    }
+
+    /// Vulkan API version to request, see `vk::make_version`
+    pub fn api_version(mut self, api_version: u32) -> Self {
+        self.api_version = api_version;
+        self
+    }
+
+    /// Require an instance layer
+    pub fn instance_layer(mut self, name: *const i8) -> Self {
+        self.instance_layers.push(name);
+        self
+    }
+
+    /// Require an instance extension
+    pub fn instance_extension(mut self, name: *const i8) -> Self {
+        self.instance_extensions.push(name);
+        self
+    }
+
+    /// Require a device extension. These are checked during hardware selection.
+    pub fn device_extension(mut self, name: *const i8) -> Self {
+        self.device_extensions.push(name);
+        self
+    }
+
+    /// Enable a device extension if the selected device supports it. See
+    /// `Core::extension_enabled`.
+    pub fn optional_device_extension(mut self, name: *const i8) -> Self {
+        self.optional_device_extensions.push(name);
+        self
+    }
+
+    /// Require device features, such as `fill_mode_non_solid` or `sampler_anisotropy`, in
+    /// addition to those already required. Devices without them are rejected during hardware
+    /// selection.
+    pub fn require_features(mut self, features: vk::PhysicalDeviceFeatures) -> Self {
+        self.required_features = union_features(&self.required_features, &features);
+        self
+    }
+
+    /// Enable device features if the selected device supports them. See
+    /// `Core::enabled_features`.
+    pub fn optional_features(mut self, features: vk::PhysicalDeviceFeatures) -> Self {
+        self.optional_features = union_features(&self.optional_features, &features);
+        self
+    }
+
+    /// Policy for choosing the physical device; see `DeviceSelector`
+    pub fn device_selector(mut self, selector: DeviceSelector) -> Self {
+        self.device_selector = selector;
+        self
+    }
+
+    /// Preferred swapchain formats and present modes; see `SurfacePreferences`
+    pub fn surface_preferences(mut self, preferences: SurfacePreferences) -> Self {
+        self.surface_preferences = preferences;
+        self
+    }
+
+    /// Check the requested layers and extensions against those available and build the setup
+    pub fn build(self) -> Result<VulkanSetup> {
+        let entry = EntryLoader::new()?;
+        self.build_with(&entry)
+    }
+
+    /// Like `build`, using an existing entry loader
+    pub fn build_with(self, entry: &DefaultEntryLoader) -> Result<VulkanSetup> {
+        let mut setup = VulkanSetup {
+            instance_layers: self.instance_layers,
+            instance_extensions: self.instance_extensions,
+            device_layers: Vec::new(),
+            device_extensions: self.device_extensions,
+            optional_device_extensions: self.optional_device_extensions,
+            required_features: self.required_features,
+            optional_features: self.optional_features,
+            api_version: self.api_version,
+            panic_on_validation_error: self.panic_on_validation_error,
+            device_selector: self.device_selector,
+            surface_preferences: self.surface_preferences,
+        };
+
+        if self.validation {
+            let validation_layer = unsafe { CStr::from_ptr(LAYER_KHRONOS_VALIDATION) };
+            if contains_name(&available_instance_layers(entry)?, validation_layer) {
+                setup.instance_layers.push(LAYER_KHRONOS_VALIDATION);
+                setup.device_layers.push(LAYER_KHRONOS_VALIDATION);
+
+                let debug_utils = unsafe { CStr::from_ptr(EXT_DEBUG_UTILS_EXTENSION_NAME) };
+                let extensions = available_instance_extensions(entry, &setup.instance_layers)?;
+                if contains_name(&extensions, debug_utils) {
+                    setup
+                        .instance_extensions
+                        .push(EXT_DEBUG_UTILS_EXTENSION_NAME);
+                } else {
+                    log::warn!("Validation enabled, but {:?} is not available", debug_utils);
+                }
+            } else {
+                log::warn!(
+                    "Validation requested, but {:?} is not installed; continuing without validation",
+                    validation_layer
+                );
+            }
+        }
+
+        check_instance_support(entry, &setup)?;
+        Ok(setup)
+    }
+}
@@ -0,0 +1,355 @@
+use crate::mem_objects::MemObject;
+use crate::*;
+use anyhow::{ensure, format_err, Result};
+use gpu_alloc_erupt::EruptMemoryDevice as EMD;
+
+impl Texture {
+    /// Upload tightly packed `data` of the given size and format into a sampled image, generating
+    /// mipmaps with blits if `mipmaps` is set and the format supports it. Also creates a linear,
+    /// repeating sampler, and a descriptor set of `set_layout` (a single combined image sampler at
+    /// binding 0) binding the texture. Blocks until the upload has completed.
+    pub fn new(
+        core: &SharedCore,
+        width: u32,
+        height: u32,
+        format: vk::Format,
+        data: &[u8],
+        mipmaps: bool,
+        set_layout: vk::DescriptorSetLayout,
+    ) -> Result<Self> {
+        ensure!(width > 0 && height > 0, "Textures must not be empty");
+        let texel_size = texel_size(format)
+            .ok_or_else(|| format_err!("Unsupported texture format {:?}", format))?;
+        let expected = width as usize * height as usize * texel_size as usize;
+        ensure!(
+            data.len() == expected,
+            "Expected {} bytes of texture data, got {}",
+            expected,
+            data.len()
+        );
+
+        let mip_levels = if mipmaps && supports_blit(core, format) {
+            32 - width.max(height).leading_zeros()
+        } else {
+            if mipmaps {
+                log::warn!(
+                    "{:?} does not support linear blits; mipmaps skipped",
+                    format
+                );
+            }
+            1
+        };
+
+        let extent = vk::Extent2D { width, height };
+        let image = Image::with_mip_levels(
+            core,
+            extent,
+            format,
+            vk::ImageUsageFlags::SAMPLED
+                | vk::ImageUsageFlags::TRANSFER_DST
+                | vk::ImageUsageFlags::TRANSFER_SRC,
+            vk::ImageAspectFlags::COLOR,
+            mip_levels,
+        )?;
+        upload(core, &image, data)?;
+
+        let create_info = vk::SamplerCreateInfoBuilder::new()
+            .mag_filter(vk::Filter::LINEAR)
+            .min_filter(vk::Filter::LINEAR)
+            .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
+            .address_mode_u(vk::SamplerAddressMode::REPEAT)
+            .address_mode_v(vk::SamplerAddressMode::REPEAT)
+            .address_mode_w(vk::SamplerAddressMode::REPEAT)
+            .anisotropy_enable(false)
+            .min_lod(0.)
+            .max_lod(mip_levels as f32)
+            .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
+            .unnormalized_coordinates(false);
+        let sampler = unsafe { core.device.create_sampler(&create_info, None, None) }.result()?;
+
+        let mut texture = Self {
+            image,
+            sampler,
+            descriptor_pool: vk::DescriptorPool::null(),
+            descriptor_set: vk::DescriptorSet::null(),
+            _core: core.clone(),
+        };
+
+        // On failure, whatever has been created so far is destroyed by drop
+        let pool_sizes = [vk::DescriptorPoolSizeBuilder::new()
+            ._type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(1)];
+        let create_info = vk::DescriptorPoolCreateInfoBuilder::new()
+            .pool_sizes(&pool_sizes)
+            .max_sets(1);
+        texture.descriptor_pool =
+            unsafe { core.device.create_descriptor_pool(&create_info, None, None) }.result()?;
+
+        let set_layouts = [set_layout];
+        let allocate_info = vk::DescriptorSetAllocateInfoBuilder::new()
+            .descriptor_pool(texture.descriptor_pool)
+            .set_layouts(&set_layouts);
+        texture.descriptor_set =
+            unsafe { core.device.allocate_descriptor_sets(&allocate_info) }.result()?[0];
+
+        let image_infos = [vk::DescriptorImageInfoBuilder::new()
+            .sampler(texture.sampler)
+            .image_view(texture.image.view)
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)];
+        let writes = [vk::WriteDescriptorSetBuilder::new()
+            .dst_set(texture.descriptor_set)
+            .dst_binding(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(&image_infos)];
+        unsafe { core.device.update_descriptor_sets(&writes, &[]) };
+
+        Ok(texture)
+    }
+
+    /// Name the image, view and sampler after `name` for debugging
+    pub fn set_name(&self, name: &str) -> Result<()> {
+        self.image.set_name(name)?;
+        self._core.set_name(self.sampler, name)?;
+        self._core.set_name(self.descriptor_set, name)
+    }
+}
+
+impl Drop for Texture {
+    /// Destroy the sampler and descriptor pool; the image is dropped afterwards. The GPU must be
+    /// finished using them.
+    fn drop(&mut self) {
+        unsafe {
+            self._core
+                .device
+                .destroy_descriptor_pool(Some(self.descriptor_pool), None);
+            self._core.device.destroy_sampler(Some(self.sampler), None);
+        }
+    }
+}
+
+/// Create the layout of the descriptor sets created for each `Texture`
+pub fn create_texture_set_layout(core: &Core) -> Result<vk::DescriptorSetLayout> {
+    let bindings = [vk::DescriptorSetLayoutBindingBuilder::new()
+        .binding(0)
+        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT)];
+    let create_info = vk::DescriptorSetLayoutCreateInfoBuilder::new().bindings(&bindings);
+    Ok(unsafe {
+        core.device
+            .create_descriptor_set_layout(&create_info, None, None)
+    }
+    .result()?)
+}
+
+/// Copy `data` into mip level 0 of `image` through a staging buffer on the graphics queue, fill
+/// the remaining levels by blitting each from the one before, and transition every level to
+/// SHADER_READ_ONLY_OPTIMAL
+fn upload(core: &SharedCore, image: &Image, data: &[u8]) -> Result<()> {
+    let create_info = vk::BufferCreateInfoBuilder::new()
+        .size(data.len() as u64)
+        .usage(vk::BufferUsageFlags::TRANSFER_SRC)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE);
+    let mut staging = MemObject::<vk::Buffer>::new(
+        core,
+        create_info,
+        gpu_alloc::UsageFlags::UPLOAD | gpu_alloc::UsageFlags::HOST_ACCESS,
+    )?;
+    unsafe {
+        staging
+            .memory_mut()
+            .write_bytes(EMD::wrap(&core.device), 0, data)?;
+    }
+
+    let device = &core.device;
+    crate::transfer::one_shot(
+        core,
+        core.graphics_queue_family,
+        core.graphics_queue,
+        |command_buffer| unsafe {
+            let barrier = |level, levels, old_layout, new_layout, src_access, dst_access| {
+                vk::ImageMemoryBarrierBuilder::new()
+                    .image(image.image)
+                    .old_layout(old_layout)
+                    .new_layout(new_layout)
+                    .src_access_mask(src_access)
+                    .dst_access_mask(dst_access)
+                    .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .subresource_range(color_range(level, levels))
+            };
+
+            // Every level starts as a transfer destination
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::PipelineStageFlags::TRANSFER,
+                None,
+                &[],
+                &[],
+                &[barrier(
+                    0,
+                    image.mip_levels,
+                    vk::ImageLayout::UNDEFINED,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    vk::AccessFlags::empty(),
+                    vk::AccessFlags::TRANSFER_WRITE,
+                )],
+            );
+
+            let region = vk::BufferImageCopyBuilder::new()
+                .buffer_offset(0)
+                .buffer_row_length(0)
+                .buffer_image_height(0)
+                .image_subresource(color_layers(0))
+                .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+                .image_extent(vk::Extent3D {
+                    width: image.extent.width,
+                    height: image.extent.height,
+                    depth: 1,
+                });
+            device.cmd_copy_buffer_to_image(
+                command_buffer,
+                staging.instance,
+                image.image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[region],
+            );
+
+            let mut size = (image.extent.width as i32, image.extent.height as i32);
+            for level in 1..image.mip_levels {
+                // The previous level is complete; read from it
+                device.cmd_pipeline_barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::PipelineStageFlags::TRANSFER,
+                    None,
+                    &[],
+                    &[],
+                    &[barrier(
+                        level - 1,
+                        1,
+                        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                        vk::AccessFlags::TRANSFER_WRITE,
+                        vk::AccessFlags::TRANSFER_READ,
+                    )],
+                );
+
+                let next = ((size.0 / 2).max(1), (size.1 / 2).max(1));
+                let blit = vk::ImageBlitBuilder::new()
+                    .src_subresource(color_layers(level - 1))
+                    .src_offsets([
+                        vk::Offset3D { x: 0, y: 0, z: 0 },
+                        vk::Offset3D {
+                            x: size.0,
+                            y: size.1,
+                            z: 1,
+                        },
+                    ])
+                    .dst_subresource(color_layers(level))
+                    .dst_offsets([
+                        vk::Offset3D { x: 0, y: 0, z: 0 },
+                        vk::Offset3D {
+                            x: next.0,
+                            y: next.1,
+                            z: 1,
+                        },
+                    ]);
+                device.cmd_blit_image(
+                    command_buffer,
+                    image.image,
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    image.image,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    &[blit],
+                    vk::Filter::LINEAR,
+                );
+
+                device.cmd_pipeline_barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::PipelineStageFlags::FRAGMENT_SHADER,
+                    None,
+                    &[],
+                    &[],
+                    &[barrier(
+                        level - 1,
+                        1,
+                        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                        vk::AccessFlags::TRANSFER_READ,
+                        vk::AccessFlags::SHADER_READ,
+                    )],
+                );
+                size = next;
+            }
+
+            // The last level was only ever written
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                None,
+                &[],
+                &[],
+                &[barrier(
+                    image.mip_levels - 1,
+                    1,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    vk::AccessFlags::TRANSFER_WRITE,
+                    vk::AccessFlags::SHADER_READ,
+                )],
+            );
+        },
+    )
+}
+
+fn color_range(base_mip_level: u32, level_count: u32) -> vk::ImageSubresourceRange {
+    vk::ImageSubresourceRangeBuilder::new()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .base_mip_level(base_mip_level)
+        .level_count(level_count)
+        .base_array_layer(0)
+        .layer_count(1)
+        .build()
+}
+
+fn color_layers(mip_level: u32) -> vk::ImageSubresourceLayers {
+    vk::ImageSubresourceLayersBuilder::new()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .mip_level(mip_level)
+        .base_array_layer(0)
+        .layer_count(1)
+        .build()
+}
+
+/// Whether `format` may be used as both source and destination of a linearly filtered blit
+fn supports_blit(core: &Core, format: vk::Format) -> bool {
+    let properties = unsafe {
+        core.instance
+            .get_physical_device_format_properties(core.physical_device, format, None)
+    };
+    properties.optimal_tiling_features.contains(
+        vk::FormatFeatureFlags::BLIT_SRC
+            | vk::FormatFeatureFlags::BLIT_DST
+            | vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR,
+    )
+}
+
+/// Size in bytes of a single texel of the given format
+fn texel_size(format: vk::Format) -> Option<u32> {
+    Some(match format {
+        vk::Format::R8_UNORM | vk::Format::R8_SRGB => 1,
+        vk::Format::R8G8_UNORM | vk::Format::R8G8_SRGB => 2,
+        vk::Format::R8G8B8A8_UNORM
+        | vk::Format::R8G8B8A8_SRGB
+        | vk::Format::B8G8R8A8_UNORM
+        | vk::Format::B8G8R8A8_SRGB
+        | vk::Format::R32_SFLOAT => 4,
+        vk::Format::R16G16B16A16_SFLOAT => 8,
+        vk::Format::R32G32B32A32_SFLOAT => 16,
+        _ => return None,
+    })
+}
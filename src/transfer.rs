@@ -0,0 +1,436 @@
+use crate::mem_objects::MemObject;
+use crate::*;
+use anyhow::Result;
+use gpu_alloc_erupt::EruptMemoryDevice as EMD;
+
+/// Record commands into a temporary command buffer with `record`, submit them to `queue` and
+/// block until they have completed
+pub fn one_shot(
+    core: &Core,
+    queue_family: u32,
+    queue: vk::Queue,
+    record: impl FnOnce(vk::CommandBuffer),
+) -> Result<()> {
+    let device = &core.device;
+    let create_info = vk::CommandPoolCreateInfoBuilder::new()
+        .queue_family_index(queue_family)
+        .flags(vk::CommandPoolCreateFlags::TRANSIENT);
+    let command_pool = unsafe { device.create_command_pool(&create_info, None, None) }.result()?;
+
+    let result = (|| {
+        let allocate_info = vk::CommandBufferAllocateInfoBuilder::new()
+            .command_pool(command_pool)
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_buffer_count(1);
+        let command_buffer =
+            unsafe { device.allocate_command_buffers(&allocate_info) }.result()?[0];
+
+        let begin_info = vk::CommandBufferBeginInfoBuilder::new()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+        unsafe { device.begin_command_buffer(command_buffer, &begin_info) }.result()?;
+        record(command_buffer);
+        unsafe { device.end_command_buffer(command_buffer) }.result()?;
+
+        let create_info = vk::FenceCreateInfoBuilder::new();
+        let fence = unsafe { device.create_fence(&create_info, None, None) }.result()?;
+        let command_buffers = [command_buffer];
+        let submit_info = vk::SubmitInfoBuilder::new().command_buffers(&command_buffers);
+        let result = unsafe {
+            device
+                .queue_submit(queue, &[submit_info], Some(fence))
+                .result()
+                .and_then(|_| device.wait_for_fences(&[fence], true, u64::MAX).result())
+        };
+        unsafe { device.destroy_fence(Some(fence), None) };
+        result?;
+        core.check_validation()
+    })();
+
+    unsafe { device.destroy_command_pool(Some(command_pool), None) };
+    result
+}
+
+/// Work submitted to the utility queue, which may still be executing. Buffers written by the
+/// work are released to the graphics queue family, and must be acquired with `record_acquire`
+/// (or `finish`) before use on the graphics queue. Dropping a submission blocks until it has
+/// completed.
+pub struct Submission {
+    fence: vk::Fence,
+    command_pool: vk::CommandPool,
+    submitted: bool,
+    staging: Vec<MemObject<vk::Buffer>>,
+    /// Buffer regions (buffer, offset, size) released to the graphics queue family
+    released: Vec<(vk::Buffer, u64, u64)>,
+    core: SharedCore,
+}
+
+impl Submission {
+    fn new(core: &SharedCore) -> Result<Self> {
+        let create_info = vk::CommandPoolCreateInfoBuilder::new()
+            .queue_family_index(core.utility_queue_family)
+            .flags(vk::CommandPoolCreateFlags::TRANSIENT);
+        let command_pool =
+            unsafe { core.device.create_command_pool(&create_info, None, None) }.result()?;
+        let create_info = vk::FenceCreateInfoBuilder::new();
+        let fence = match unsafe { core.device.create_fence(&create_info, None, None) }.result() {
+            Ok(fence) => fence,
+            Err(e) => {
+                unsafe { core.device.destroy_command_pool(Some(command_pool), None) };
+                return Err(e.into());
+            }
+        };
+        Ok(Self {
+            fence,
+            command_pool,
+            submitted: false,
+            staging: Vec::new(),
+            released: Vec::new(),
+            core: core.clone(),
+        })
+    }
+
+    /// Record commands with `record` and submit them to the utility queue
+    fn submit(&mut self, record: impl FnOnce(vk::CommandBuffer)) -> Result<()> {
+        let device = &self.core.device;
+        let allocate_info = vk::CommandBufferAllocateInfoBuilder::new()
+            .command_pool(self.command_pool)
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_buffer_count(1);
+        let command_buffer =
+            unsafe { device.allocate_command_buffers(&allocate_info) }.result()?[0];
+
+        let begin_info = vk::CommandBufferBeginInfoBuilder::new()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+        unsafe { device.begin_command_buffer(command_buffer, &begin_info) }.result()?;
+        record(command_buffer);
+        unsafe { device.end_command_buffer(command_buffer) }.result()?;
+
+        let command_buffers = [command_buffer];
+        let submit_info = vk::SubmitInfoBuilder::new().command_buffers(&command_buffers);
+        unsafe { device.queue_submit(self.core.utility_queue, &[submit_info], Some(self.fence)) }
+            .result()?;
+        self.submitted = true;
+        self.core.check_validation()
+    }
+
+    /// Whether the work has completed. A submission with no work is always complete.
+    pub fn is_complete(&self) -> Result<bool> {
+        if !self.submitted {
+            return Ok(true);
+        }
+        let status = unsafe { self.core.device.get_fence_status(self.fence) };
+        match status.raw {
+            vk::Result::SUCCESS => Ok(true),
+            vk::Result::NOT_READY => Ok(false),
+            _ => Err(status.result().unwrap_err().into()),
+        }
+    }
+
+    /// Block until the work has completed
+    pub fn wait(&self) -> Result<()> {
+        if !self.submitted {
+            return Ok(());
+        }
+        unsafe {
+            self.core
+                .device
+                .wait_for_fences(&[self.fence], true, u64::MAX)
+                .result()?;
+        }
+        Ok(())
+    }
+
+    /// Whether any buffers must be acquired by the graphics queue family before use
+    pub fn needs_acquire(&self) -> bool {
+        !self.released.is_empty()
+            && self.core.utility_queue_family != self.core.graphics_queue_family
+    }
+
+    /// Record the acquiring half of the queue family ownership transfers into `command_buffer`,
+    /// which must be submitted to the graphics queue after the work has completed. Must be
+    /// recorded outside of a render pass.
+    pub fn record_acquire(&self, command_buffer: vk::CommandBuffer) {
+        if !self.needs_acquire() {
+            return;
+        }
+        let barriers = ownership_barriers(&self.core, &self.released)
+            .into_iter()
+            .map(|barrier| barrier.dst_access_mask(vk::AccessFlags::MEMORY_READ))
+            .collect::<Vec<_>>();
+        unsafe {
+            self.core.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                None,
+                &[],
+                &barriers,
+                &[],
+            );
+        }
+    }
+
+    /// Block until the work has completed, then acquire any released buffers on the graphics
+    /// queue
+    pub fn finish(self) -> Result<()> {
+        self.wait()?;
+        if self.needs_acquire() {
+            one_shot(
+                &self.core,
+                self.core.graphics_queue_family,
+                self.core.graphics_queue,
+                |command_buffer| self.record_acquire(command_buffer),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Submission {
+    fn drop(&mut self) {
+        unsafe {
+            if self.submitted {
+                let _ = self
+                    .core
+                    .device
+                    .wait_for_fences(&[self.fence], true, u64::MAX);
+            }
+            self.staging.clear();
+            self.core.device.destroy_fence(Some(self.fence), None);
+            self.core
+                .device
+                .destroy_command_pool(Some(self.command_pool), None);
+        }
+    }
+}
+
+/// Submit commands recorded by `record` to the utility queue without waiting for them to
+/// complete. Suitable for compute and transfer work.
+pub fn submit(core: &SharedCore, record: impl FnOnce(vk::CommandBuffer)) -> Result<Submission> {
+    let mut submission = Submission::new(core)?;
+    submission.submit(record)?;
+    Ok(submission)
+}
+
+/// Like `submit`, but `release` is released to the graphics queue family after `record`, from
+/// writes with `src_access` at `src_stage`. The buffers must be exclusively owned by the utility
+/// queue family.
+pub(crate) fn submit_releasing(
+    core: &SharedCore,
+    release: &[vk::Buffer],
+    src_stage: vk::PipelineStageFlags,
+    src_access: vk::AccessFlags,
+    record: impl FnOnce(vk::CommandBuffer),
+) -> Result<Submission> {
+    let mut submission = Submission::new(core)?;
+    submission.released = release
+        .iter()
+        .map(|&buffer| (buffer, 0, vk::WHOLE_SIZE))
+        .collect();
+    let released = submission.released.clone();
+    submission.submit(|command_buffer| {
+        record(command_buffer);
+        record_release(core, command_buffer, &released, src_stage, src_access);
+    })?;
+    Ok(submission)
+}
+
+/// Copy each of `regions` (destination buffer, and bytes to be written at its start) through a
+/// single host-visible staging buffer, using the utility queue. The destination buffers must be
+/// exclusively owned by the utility queue family, and are released to the graphics queue family.
+/// Does not wait for the copy to complete.
+pub fn upload_buffers_async(
+    core: &SharedCore,
+    regions: &[(vk::Buffer, &[u8])],
+) -> Result<Submission> {
+    let mut submission = Submission::new(core)?;
+    let total_size: usize = regions.iter().map(|(_, data)| data.len()).sum();
+    if total_size == 0 {
+        return Ok(submission);
+    }
+
+    let create_info = vk::BufferCreateInfoBuilder::new()
+        .size(total_size as u64)
+        .usage(vk::BufferUsageFlags::TRANSFER_SRC)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE);
+    submission.staging.push(MemObject::<vk::Buffer>::new(
+        core,
+        create_info,
+        gpu_alloc::UsageFlags::UPLOAD | gpu_alloc::UsageFlags::HOST_ACCESS,
+    )?);
+    let staging = &mut submission.staging[0];
+
+    let mut offset = 0;
+    for (dst, data) in regions {
+        if data.is_empty() {
+            continue;
+        }
+        unsafe {
+            staging
+                .memory_mut()
+                .write_bytes(EMD::wrap(&core.device), offset, data)?;
+        }
+        submission.released.push((*dst, 0, data.len() as u64));
+        offset += data.len() as u64;
+    }
+
+    let staging = staging.instance;
+    let released = submission.released.clone();
+    submission.submit(|command_buffer| {
+        let mut offset = 0;
+        for &(dst, _, size) in &released {
+            let region = vk::BufferCopyBuilder::new()
+                .src_offset(offset)
+                .dst_offset(0)
+                .size(size);
+            unsafe {
+                core.device
+                    .cmd_copy_buffer(command_buffer, staging, dst, &[region]);
+            }
+            offset += size;
+        }
+
+        // Release the destination buffers to the graphics queue family
+        record_release(
+            core,
+            command_buffer,
+            &released,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::AccessFlags::TRANSFER_WRITE,
+        );
+    })?;
+
+    Ok(submission)
+}
+
+/// Like `upload_buffers_async`, but blocks until the copy has completed and the buffers have
+/// been acquired by the graphics queue family
+pub fn upload_buffers(core: &SharedCore, regions: &[(vk::Buffer, &[u8])]) -> Result<()> {
+    upload_buffers_async(core, regions)?.finish()
+}
+
+/// Record the releasing half of the queue family ownership transfers of `released`, after
+/// writes with `src_access` at `src_stage`
+fn record_release(
+    core: &Core,
+    command_buffer: vk::CommandBuffer,
+    released: &[(vk::Buffer, u64, u64)],
+    src_stage: vk::PipelineStageFlags,
+    src_access: vk::AccessFlags,
+) {
+    if released.is_empty() || core.utility_queue_family == core.graphics_queue_family {
+        return;
+    }
+    let barriers = ownership_barriers(core, released)
+        .into_iter()
+        .map(|barrier| barrier.src_access_mask(src_access))
+        .collect::<Vec<_>>();
+    unsafe {
+        core.device.cmd_pipeline_barrier(
+            command_buffer,
+            src_stage,
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            None,
+            &[],
+            &barriers,
+            &[],
+        );
+    }
+}
+
+/// Barriers transferring ownership of `regions` from the utility to the graphics queue family,
+/// with no access masks set
+fn ownership_barriers(
+    core: &Core,
+    regions: &[(vk::Buffer, u64, u64)],
+) -> Vec<vk::BufferMemoryBarrierBuilder<'static>> {
+    regions
+        .iter()
+        .map(|&(buffer, offset, size)| {
+            vk::BufferMemoryBarrierBuilder::new()
+                .src_queue_family_index(core.utility_queue_family)
+                .dst_queue_family_index(core.graphics_queue_family)
+                .buffer(buffer)
+                .offset(offset)
+                .size(size)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A headless core on any available device, including CPU implementations
+    fn test_core() -> Result<SharedCore> {
+        let app_info = ApplicationInfo {
+            name: "transfer test".into(),
+            version: 1,
+        };
+        let setup = VulkanSetup::builder().build()?;
+        Ok(crate::headless::basics(&app_info, &setup)?.1)
+    }
+
+    #[test]
+    #[ignore = "requires a Vulkan device; run with --ignored"]
+    fn empty_upload_completes() -> Result<()> {
+        let core = test_core()?;
+        let submission = upload_buffers_async(&core, &[])?;
+        assert!(submission.is_complete()?);
+        assert!(!submission.needs_acquire());
+        submission.finish()
+    }
+
+    #[test]
+    #[ignore = "requires a Vulkan device; run with --ignored"]
+    fn upload_is_read_back() -> Result<()> {
+        let core = test_core()?;
+        let data: Vec<u8> = (0..=255).collect();
+        let create_info = vk::BufferCreateInfoBuilder::new()
+            .size(data.len() as u64)
+            .usage(vk::BufferUsageFlags::TRANSFER_DST)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        let mut buffer = MemObject::<vk::Buffer>::new(
+            &core,
+            create_info,
+            gpu_alloc::UsageFlags::DOWNLOAD | gpu_alloc::UsageFlags::HOST_ACCESS,
+        )?;
+
+        let submission = upload_buffers_async(&core, &[(buffer.instance, &data)])?;
+        assert_eq!(
+            submission.needs_acquire(),
+            core.utility_queue_family != core.graphics_queue_family
+        );
+        submission.finish()?;
+
+        // Make the copy visible to the host
+        one_shot(
+            &core,
+            core.graphics_queue_family,
+            core.graphics_queue,
+            |command_buffer| unsafe {
+                let barrier = vk::MemoryBarrierBuilder::new()
+                    .src_access_mask(vk::AccessFlags::MEMORY_WRITE)
+                    .dst_access_mask(vk::AccessFlags::HOST_READ);
+                core.device.cmd_pipeline_barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::ALL_COMMANDS,
+                    vk::PipelineStageFlags::HOST,
+                    None,
+                    &[barrier],
+                    &[],
+                    &[],
+                );
+            },
+        )?;
+
+        let mut read = vec![0; data.len()];
+        unsafe {
+            buffer
+                .memory_mut()
+                .read_bytes(EMD::wrap(&core.device), 0, &mut read)?;
+        }
+        assert_eq!(read, data);
+        Ok(())
+    }
+}
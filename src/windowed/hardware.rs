@@ -1,6 +1,6 @@
 use super::{COLOR_FORMAT, COLOR_SPACE};
 use crate::*;
-use anyhow::{format_err, Result};
+use anyhow::{ensure, format_err, Result};
 use erupt::{
     extensions::khr_surface,
     InstanceLoader,
@@ -39,7 +39,7 @@ pub fn find_surface_queue_family(
     Err(format_err!("No suitable graphics queue family found"))
 }
 
-/// Finds a COMPUTE and TRANSFER queue
+/// Finds a COMPUTE and TRANSFER queue, preferring one without GRAPHICS
 pub fn find_utility_queue_family(
     instance: &InstanceLoader,
     physical_device: vk::PhysicalDevice,
@@ -49,54 +49,136 @@ pub fn find_utility_queue_family(
             .get_physical_device_queue_family_properties(physical_device, None)
             .into_iter()
     };
+    // Prefer a family without graphics, so that utility work can run alongside rendering
+    let mut fallback = None;
     for (i, properties) in qf_properties.enumerate() {
         let has_compute = properties.queue_flags.contains(vk::QueueFlags::COMPUTE);
         let has_transfer = properties.queue_flags.contains(vk::QueueFlags::TRANSFER);
+        let has_graphics = properties.queue_flags.contains(vk::QueueFlags::GRAPHICS);
 
         if has_compute && has_transfer {
-            return Ok(i as u32);
+            if !has_graphics {
+                return Ok(i as u32);
+            }
+            fallback.get_or_insert(i as u32);
+        }
+    }
+    fallback.ok_or_else(|| format_err!("No suitable utility queue family found"))
+}
+
+/// Ordered preferences for the swapchain's format and present mode. The first supported entry
+/// of each list is used.
+#[derive(Clone, Debug)]
+pub struct SurfacePreferences {
+    /// If none are supported, an sRGB format is chosen if there is one, otherwise the first
+    /// format the surface supports
+    pub formats: Vec<khr_surface::SurfaceFormatKHR>,
+    /// If none are supported, FIFO is used, which every surface supports. FIFO is vsync,
+    /// IMMEDIATE suits benchmarks, and FIFO_RELAXED tears only when a frame is late.
+    pub present_modes: Vec<khr_surface::PresentModeKHR>,
+}
+
+impl Default for SurfacePreferences {
+    /// `COLOR_FORMAT` or its RGBA equivalent in `COLOR_SPACE`, presented with MAILBOX
+    fn default() -> Self {
+        let format = |format| khr_surface::SurfaceFormatKHR {
+            format,
+            color_space: COLOR_SPACE,
+        };
+        Self {
+            formats: vec![format(COLOR_FORMAT), format(vk::Format::R8G8B8A8_SRGB)],
+            present_modes: vec![khr_surface::PresentModeKHR::MAILBOX_KHR],
+        }
+    }
+}
+
+impl SurfacePreferences {
+    /// Default formats, with the given present modes
+    pub fn with_present_modes(present_modes: &[khr_surface::PresentModeKHR]) -> Self {
+        Self {
+            present_modes: present_modes.to_vec(),
+            ..Default::default()
         }
     }
-    Err(format_err!("No suitable utility queue family found"))
 }
 
-/// Find a surface format compatible with COLOR_FORMAT (and SRGB_NONLINEAR_KHR if available)
+/// Select the first of `preferences` the surface supports; see `SurfacePreferences::formats`
 pub fn select_surface_format(
     instance: &InstanceLoader,
     physical_device: vk::PhysicalDevice,
     surface: khr_surface::SurfaceKHR,
+    preferences: &[khr_surface::SurfaceFormatKHR],
 ) -> Result<khr_surface::SurfaceFormatKHR> {
     let formats = unsafe {
         instance
             .get_physical_device_surface_formats_khr(physical_device, surface, None)
             .result()?
     };
-    match formats
-        .into_iter()
-        .find(|&surface_format| {
-            surface_format.format == COLOR_FORMAT
-                && surface_format.color_space == COLOR_SPACE
-        })
-    {
-        Some(surface_format) => Ok(surface_format),
-        None => return Err(format_err!("No suitable surface format found.")),
+    choose_surface_format(&formats, preferences)
+}
+
+/// Choose the first of `preferences` among the `available` formats of a surface; see
+/// `SurfacePreferences::formats`
+pub fn choose_surface_format(
+    available: &[khr_surface::SurfaceFormatKHR],
+    preferences: &[khr_surface::SurfaceFormatKHR],
+) -> Result<khr_surface::SurfaceFormatKHR> {
+    // A single undefined format means the surface has no preference
+    if let [only] = available {
+        if only.format == vk::Format::UNDEFINED {
+            return preferences
+                .first()
+                .copied()
+                .ok_or_else(|| format_err!("Surface has no preferred format; one must be given"));
+        }
     }
+
+    let supported = |wanted: &khr_surface::SurfaceFormatKHR| {
+        available
+            .iter()
+            .any(|f| f.format == wanted.format && f.color_space == wanted.color_space)
+    };
+    let is_srgb = |f: &&khr_surface::SurfaceFormatKHR| {
+        f.color_space == COLOR_SPACE
+            && matches!(
+                f.format,
+                vk::Format::B8G8R8A8_SRGB | vk::Format::R8G8B8A8_SRGB
+            )
+    };
+    preferences
+        .iter()
+        .find(|wanted| supported(wanted))
+        .or_else(|| available.iter().find(is_srgb))
+        .or_else(|| available.first())
+        .copied()
+        .ok_or_else(|| format_err!("Surface supports no formats"))
 }
 
-/// Select a present mode for this surface
+/// Select the first of `preferences` the surface supports, falling back to FIFO
 pub fn select_present_mode(
     instance: &InstanceLoader,
     physical_device: vk::PhysicalDevice,
     surface: khr_surface::SurfaceKHR,
+    preferences: &[khr_surface::PresentModeKHR],
 ) -> Result<khr_surface::PresentModeKHR> {
-    unsafe {
-        Ok(instance
+    let present_modes = unsafe {
+        instance
             .get_physical_device_surface_present_modes_khr(physical_device, surface, None)
             .result()?
-            .into_iter()
-            .find(|present_mode| present_mode == &khr_surface::PresentModeKHR::MAILBOX_KHR)
-            .unwrap_or(khr_surface::PresentModeKHR::FIFO_KHR))
-    }
+    };
+    Ok(choose_present_mode(&present_modes, preferences))
+}
+
+/// Choose the first of `preferences` among the `available` present modes, falling back to FIFO
+pub fn choose_present_mode(
+    available: &[khr_surface::PresentModeKHR],
+    preferences: &[khr_surface::PresentModeKHR],
+) -> khr_surface::PresentModeKHR {
+    preferences
+        .iter()
+        .copied()
+        .find(|present_mode| available.contains(present_mode))
+        .unwrap_or(khr_surface::PresentModeKHR::FIFO_KHR)
 }
 
 /// Check that the given physical_device supports all of requested_extensions
@@ -147,15 +229,21 @@ pub fn select_hardware_physical_device(
     })
 }
 
-/// Find a valid SurfaceInfo for this surface
+/// Find a valid SurfaceInfo for this surface, following `preferences`
 pub fn select_surface_info(
     instance: &InstanceLoader,
     physical_device: vk::PhysicalDevice,
     surface: khr_surface::SurfaceKHR,
+    preferences: &SurfacePreferences,
 ) -> Result<SurfaceInfo> {
     Ok(SurfaceInfo {
-        format: select_surface_format(instance, physical_device, surface)?,
-        present_mode: select_present_mode(instance, physical_device, surface)?,
+        format: select_surface_format(instance, physical_device, surface, &preferences.formats)?,
+        present_mode: select_present_mode(
+            instance,
+            physical_device,
+            surface,
+            &preferences.present_modes,
+        )?,
     })
 }
 
@@ -168,24 +256,589 @@ pub fn score_hardware_config(hardware: &HardwareSelection) -> i32 {
     }
 }
 
-/// Query for hardware with the right properties for windowed mode
+/// Query for hardware with the right properties for windowed mode, chosen by `selector`
 pub fn query(
     instance: &InstanceLoader,
     surface: khr_surface::SurfaceKHR,
     device_extensions: &[*const c_char],
+    selector: &DeviceSelector,
+    preferences: &SurfacePreferences,
 ) -> Result<(HardwareSelection, SurfaceInfo)> {
-    unsafe { instance.enumerate_physical_devices(None) }
-        .result()?
-        .into_iter()
-        .map(|physical_device| {
+    selector.select(
+        instance,
+        device_extensions,
+        |physical_device| {
             let hardware = select_hardware_physical_device(instance, physical_device, surface)?;
-            let surface = select_surface_info(instance, physical_device, surface)?;
-            let _ = check_supported_extensions(instance, physical_device, device_extensions)?;
+            let surface = select_surface_info(instance, physical_device, surface, preferences)?;
             Ok((hardware, surface))
+        },
+        |(hardware, _)| score_hardware_config(hardware),
+    )
+}
+
+/// Environment variable consulted by `DeviceSelector` by default. Holds a device index, a
+/// `vendor:device` ID pair in hex, a vendor ID alone with a `0x` prefix, or otherwise a substring
+/// of a device name.
+pub const DEVICE_ENV_VAR: &str = "KLYSTRON_DEVICE";
+
+/// A specific device to select
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DeviceChoice {
+    /// Index in the order devices are enumerated
+    Index(usize),
+    /// Case-insensitive substring of the device name
+    Name(String),
+    Id {
+        vendor_id: u32,
+        device_id: Option<u32>,
+    },
+}
+
+impl DeviceChoice {
+    /// Parse the format described by `DEVICE_ENV_VAR`
+    pub fn parse(value: &str) -> Self {
+        let value = value.trim();
+        if let Ok(index) = value.parse() {
+            return DeviceChoice::Index(index);
+        }
+        let hex = |s: &str| u32::from_str_radix(s.trim_start_matches("0x"), 16).ok();
+        let mut ids = value.splitn(2, ':');
+        match (ids.next().and_then(hex), ids.next().map(hex)) {
+            (Some(vendor_id), None) if value.starts_with("0x") => DeviceChoice::Id {
+                vendor_id,
+                device_id: None,
+            },
+            (Some(vendor_id), Some(Some(device_id))) => DeviceChoice::Id {
+                vendor_id,
+                device_id: Some(device_id),
+            },
+            _ => DeviceChoice::Name(value.to_owned()),
+        }
+    }
+
+    fn matches(&self, index: usize, properties: &vk::PhysicalDeviceProperties) -> bool {
+        match self {
+            DeviceChoice::Index(i) => *i == index,
+            DeviceChoice::Name(name) => device_name(properties)
+                .to_lowercase()
+                .contains(&name.to_lowercase()),
+            DeviceChoice::Id {
+                vendor_id,
+                device_id,
+            } => {
+                properties.vendor_id == *vendor_id
+                    && device_id.is_none_or(|id| properties.device_id == id)
+            }
+        }
+    }
+}
+
+/// Test of a device's limits; see `DeviceSelector::require_limit`
+pub type LimitCheck = fn(&vk::PhysicalDeviceLimits) -> bool;
+
+/// Policy for choosing a physical device. Among the devices meeting every requirement, the
+/// chosen device is selected if there is one, otherwise the best by `score_hardware_config`.
+#[derive(Clone, Debug)]
+pub struct DeviceSelector {
+    choice: Option<DeviceChoice>,
+    env_var: Option<String>,
+    features: vk::PhysicalDeviceFeatures,
+    limits: Vec<(&'static str, LimitCheck)>,
+    allow_cpu: Option<bool>,
+}
+
+impl Default for DeviceSelector {
+    /// Any device, honouring `DEVICE_ENV_VAR`. CPU devices are allowed by default only when
+    /// headless; see `allow_cpu`.
+    fn default() -> Self {
+        Self {
+            choice: None,
+            env_var: Some(DEVICE_ENV_VAR.to_owned()),
+            features: Default::default(),
+            limits: Vec::new(),
+            allow_cpu: None,
+        }
+    }
+}
+
+/// A device considered by `DeviceSelector`
+#[derive(Clone, Debug)]
+pub struct DeviceCandidate {
+    pub index: usize,
+    pub name: String,
+    pub vendor_id: u32,
+    pub device_id: u32,
+    pub device_type: vk::PhysicalDeviceType,
+    /// Why the device cannot be used, if it cannot
+    pub rejection: Option<String>,
+}
+
+impl DeviceSelector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only select the given device. Overridden by the environment variable, if set.
+    pub fn choose(mut self, choice: DeviceChoice) -> Self {
+        self.choice = Some(choice);
+        self
+    }
+
+    /// Environment variable which overrides `choose`, or `None` to ignore the environment
+    pub fn env_var(mut self, name: Option<&str>) -> Self {
+        self.env_var = name.map(str::to_owned);
+        self
+    }
+
+    /// Reject devices lacking any feature enabled in `features`, in addition to those already
+    /// required
+    pub fn require_features(mut self, features: vk::PhysicalDeviceFeatures) -> Self {
+        self.features = union_features(&self.features, &features);
+        self
+    }
+
+    /// Reject devices whose limits fail `check`. `description` is reported on rejection.
+    pub fn require_limit(mut self, description: &'static str, check: LimitCheck) -> Self {
+        self.limits.push((description, check));
+        self
+    }
+
+    /// Allow CPU implementations such as lavapipe to be selected. Unless set, they are allowed
+    /// for headless use, where they are often all that is available, but not for windowed use.
+    pub fn allow_cpu(mut self, allow: bool) -> Self {
+        self.allow_cpu = Some(allow);
+        self
+    }
+
+    /// Allow CPU implementations if `allow_cpu` has not been set
+    pub(crate) fn allow_cpu_by_default(mut self, allow: bool) -> Self {
+        self.allow_cpu.get_or_insert(allow);
+        self
+    }
+
+    /// The device to select; from the environment variable if it is set, otherwise `choose`
+    pub fn choice(&self) -> Option<DeviceChoice> {
+        self.env_var
+            .as_ref()
+            .and_then(|name| std::env::var(name).ok())
+            .filter(|value| !value.trim().is_empty())
+            .map(|value| DeviceChoice::parse(&value))
+            .or_else(|| self.choice.clone())
+    }
+
+    /// Select a device with `hardware`, which builds a selection for a device or explains why it
+    /// is unsuitable. Every candidate is logged; on failure the error lists every rejection.
+    pub fn select<T>(
+        &self,
+        instance: &InstanceLoader,
+        device_extensions: &[*const c_char],
+        mut hardware: impl FnMut(vk::PhysicalDevice) -> Result<T>,
+        score: impl Fn(&T) -> i32,
+    ) -> Result<T> {
+        let choice = self.choice();
+        let mut candidates = Vec::new();
+        let mut best: Option<(i32, T)> = None;
+
+        let physical_devices = unsafe { instance.enumerate_physical_devices(None) }.result()?;
+        for (index, &physical_device) in physical_devices.iter().enumerate() {
+            let properties =
+                unsafe { instance.get_physical_device_properties(physical_device, None) };
+            let selection = self
+                .check(
+                    instance,
+                    physical_device,
+                    index,
+                    &properties,
+                    choice.as_ref(),
+                )
+                .and_then(|_| {
+                    check_supported_extensions(instance, physical_device, device_extensions)
+                })
+                .and_then(|_| hardware(physical_device));
+            let rejection = match selection {
+                Ok(selection) => {
+                    let score = score(&selection);
+                    if best.as_ref().is_none_or(|(best, _)| score > *best) {
+                        best = Some((score, selection));
+                    }
+                    None
+                }
+                Err(e) => Some(e.to_string()),
+            };
+            candidates.push(DeviceCandidate {
+                index,
+                name: device_name(&properties),
+                vendor_id: properties.vendor_id,
+                device_id: properties.device_id,
+                device_type: properties.device_type,
+                rejection,
+            });
+        }
+
+        let table = candidate_table(&candidates);
+        match best {
+            Some((_, selection)) => {
+                log::info!("Physical devices:\n{}", table);
+                Ok(selection)
+            }
+            None => Err(format_err!("No suitable physical device found:\n{}", table)),
+        }
+    }
+
+    fn check(
+        &self,
+        instance: &InstanceLoader,
+        physical_device: vk::PhysicalDevice,
+        index: usize,
+        properties: &vk::PhysicalDeviceProperties,
+        choice: Option<&DeviceChoice>,
+    ) -> Result<()> {
+        if let Some(choice) = choice {
+            ensure!(
+                choice.matches(index, properties),
+                "Not the chosen device {:?}",
+                choice
+            );
+        }
+        ensure!(
+            self.allow_cpu.unwrap_or(false)
+                || properties.device_type != vk::PhysicalDeviceType::CPU,
+            "CPU devices are not allowed"
+        );
+
+        let supported = unsafe { instance.get_physical_device_features(physical_device, None) };
+        let missing = missing_features(&self.features, &supported);
+        ensure!(
+            missing.is_empty(),
+            "Missing features: {}",
+            missing.join(", ")
+        );
+
+        let failed: Vec<&str> = self
+            .limits
+            .iter()
+            .filter(|(_, check)| !check(&properties.limits))
+            .map(|(description, _)| *description)
+            .collect();
+        ensure!(failed.is_empty(), "Limits not met: {}", failed.join(", "));
+        Ok(())
+    }
+}
+
+/// Format candidates as a table, one device per line
+pub fn candidate_table(candidates: &[DeviceCandidate]) -> String {
+    candidates
+        .iter()
+        .map(|candidate| {
+            format!(
+                "  [{}] {} ({:04x}:{:04x}, {:?}): {}",
+                candidate.index,
+                candidate.name,
+                candidate.vendor_id,
+                candidate.device_id,
+                candidate.device_type,
+                candidate.rejection.as_deref().unwrap_or("OK"),
+            )
         })
-        .max_by_key(|res| match res {
-            Ok((hardware, _)) => score_hardware_config(&hardware),
-            Err(_) => std::i32::MIN,
-        })
-        .unwrap_or_else(|| Err(format_err!("No physical devices found")))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn device_name(properties: &vk::PhysicalDeviceProperties) -> String {
+    unsafe { CStr::from_ptr(properties.device_name.as_ptr()) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+macro_rules! feature_sets {
+    ($($feature:ident),* $(,)?) => {
+        /// Names of the features enabled in `required` but not in `supported`
+        pub fn missing_features(
+            required: &vk::PhysicalDeviceFeatures,
+            supported: &vk::PhysicalDeviceFeatures,
+        ) -> Vec<&'static str> {
+            let mut missing = Vec::new();
+            $(if required.$feature != vk::FALSE && supported.$feature == vk::FALSE {
+                missing.push(stringify!($feature));
+            })*
+            missing
+        }
+
+        /// Features enabled in either `a` or `b`
+        pub fn union_features(
+            a: &vk::PhysicalDeviceFeatures,
+            b: &vk::PhysicalDeviceFeatures,
+        ) -> vk::PhysicalDeviceFeatures {
+            vk::PhysicalDeviceFeatures {
+                $($feature: (a.$feature != vk::FALSE || b.$feature != vk::FALSE) as vk::Bool32,)*
+            }
+        }
+
+        /// Features enabled in both `a` and `b`
+        pub fn intersect_features(
+            a: &vk::PhysicalDeviceFeatures,
+            b: &vk::PhysicalDeviceFeatures,
+        ) -> vk::PhysicalDeviceFeatures {
+            vk::PhysicalDeviceFeatures {
+                $($feature: (a.$feature != vk::FALSE && b.$feature != vk::FALSE) as vk::Bool32,)*
+            }
+        }
+    };
+}
+
+feature_sets!(
+    robust_buffer_access,
+    full_draw_index_uint32,
+    image_cube_array,
+    independent_blend,
+    geometry_shader,
+    tessellation_shader,
+    sample_rate_shading,
+    dual_src_blend,
+    logic_op,
+    multi_draw_indirect,
+    draw_indirect_first_instance,
+    depth_clamp,
+    depth_bias_clamp,
+    fill_mode_non_solid,
+    depth_bounds,
+    wide_lines,
+    large_points,
+    alpha_to_one,
+    multi_viewport,
+    sampler_anisotropy,
+    texture_compression_etc2,
+    texture_compression_astc_ldr,
+    texture_compression_bc,
+    occlusion_query_precise,
+    pipeline_statistics_query,
+    vertex_pipeline_stores_and_atomics,
+    fragment_stores_and_atomics,
+    shader_tessellation_and_geometry_point_size,
+    shader_image_gather_extended,
+    shader_storage_image_extended_formats,
+    shader_storage_image_multisample,
+    shader_storage_image_read_without_format,
+    shader_storage_image_write_without_format,
+    shader_uniform_buffer_array_dynamic_indexing,
+    shader_sampled_image_array_dynamic_indexing,
+    shader_storage_buffer_array_dynamic_indexing,
+    shader_storage_image_array_dynamic_indexing,
+    shader_clip_distance,
+    shader_cull_distance,
+    shader_float64,
+    shader_int64,
+    shader_int16,
+    shader_resource_residency,
+    shader_resource_min_lod,
+    sparse_binding,
+    sparse_residency_buffer,
+    sparse_residency_image2_d,
+    sparse_residency_image3_d,
+    sparse_residency2_samples,
+    sparse_residency4_samples,
+    sparse_residency8_samples,
+    sparse_residency16_samples,
+    sparse_residency_aliased,
+    variable_multisample_rate,
+    inherited_queries,
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn properties(name: &str, vendor_id: u32, device_id: u32) -> vk::PhysicalDeviceProperties {
+        let mut properties = vk::PhysicalDeviceProperties {
+            vendor_id,
+            device_id,
+            ..Default::default()
+        };
+        for (dst, &src) in properties.device_name.iter_mut().zip(name.as_bytes()) {
+            *dst = src as c_char;
+        }
+        properties
+    }
+
+    fn surface_format(format: vk::Format) -> khr_surface::SurfaceFormatKHR {
+        khr_surface::SurfaceFormatKHR {
+            format,
+            color_space: COLOR_SPACE,
+        }
+    }
+
+    #[test]
+    fn preferred_format_is_chosen() -> Result<()> {
+        let available = [
+            surface_format(vk::Format::R8G8B8A8_SRGB),
+            surface_format(vk::Format::B8G8R8A8_UNORM),
+        ];
+        let preferences = [
+            surface_format(vk::Format::A2B10G10R10_UNORM_PACK32),
+            surface_format(vk::Format::B8G8R8A8_UNORM),
+        ];
+        let chosen = choose_surface_format(&available, &preferences)?;
+        assert_eq!(chosen.format, vk::Format::B8G8R8A8_UNORM);
+        Ok(())
+    }
+
+    #[test]
+    fn format_falls_back_to_srgb() -> Result<()> {
+        let available = [
+            surface_format(vk::Format::B8G8R8A8_UNORM),
+            surface_format(vk::Format::R8G8B8A8_SRGB),
+        ];
+        let preferences = [surface_format(vk::Format::R16G16B16A16_SFLOAT)];
+        let chosen = choose_surface_format(&available, &preferences)?;
+        assert_eq!(chosen.format, vk::Format::R8G8B8A8_SRGB);
+        Ok(())
+    }
+
+    #[test]
+    fn format_falls_back_to_first() -> Result<()> {
+        let available = [
+            surface_format(vk::Format::B8G8R8A8_UNORM),
+            surface_format(vk::Format::R8G8B8A8_UNORM),
+        ];
+        let chosen = choose_surface_format(&available, &SurfacePreferences::default().formats)?;
+        assert_eq!(chosen.format, vk::Format::B8G8R8A8_UNORM);
+        assert!(choose_surface_format(&[], &SurfacePreferences::default().formats).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn undefined_format_takes_first_preference() -> Result<()> {
+        let available = [surface_format(vk::Format::UNDEFINED)];
+        let preferences = [
+            surface_format(vk::Format::R16G16B16A16_SFLOAT),
+            surface_format(vk::Format::B8G8R8A8_SRGB),
+        ];
+        let chosen = choose_surface_format(&available, &preferences)?;
+        assert_eq!(chosen.format, vk::Format::R16G16B16A16_SFLOAT);
+        assert!(choose_surface_format(&available, &[]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn present_mode_preference() {
+        use khr_surface::PresentModeKHR as Mode;
+        let available = [Mode::FIFO_KHR, Mode::IMMEDIATE_KHR];
+        assert_eq!(
+            choose_present_mode(&available, &[Mode::MAILBOX_KHR, Mode::IMMEDIATE_KHR]),
+            Mode::IMMEDIATE_KHR
+        );
+        assert_eq!(
+            choose_present_mode(&available, &[Mode::MAILBOX_KHR]),
+            Mode::FIFO_KHR
+        );
+        assert_eq!(choose_present_mode(&available, &[]), Mode::FIFO_KHR);
+    }
+
+    #[test]
+    fn parse_index() {
+        assert_eq!(DeviceChoice::parse("1"), DeviceChoice::Index(1));
+        assert_eq!(DeviceChoice::parse(" 0 "), DeviceChoice::Index(0));
+    }
+
+    #[test]
+    fn parse_ids() {
+        let vendor = DeviceChoice::Id {
+            vendor_id: 0x10de,
+            device_id: None,
+        };
+        assert_eq!(DeviceChoice::parse("0x10de"), vendor);
+        let device = DeviceChoice::Id {
+            vendor_id: 0x10de,
+            device_id: Some(0x1b80),
+        };
+        assert_eq!(DeviceChoice::parse("10de:1b80"), device);
+        assert_eq!(DeviceChoice::parse("0x10de:0x1b80"), device);
+    }
+
+    #[test]
+    fn parse_name() {
+        assert_eq!(
+            DeviceChoice::parse("GeForce"),
+            DeviceChoice::Name("GeForce".into())
+        );
+        // A vendor ID alone needs its prefix, to tell it apart from a name
+        assert_eq!(
+            DeviceChoice::parse("10de"),
+            DeviceChoice::Name("10de".into())
+        );
+        assert_eq!(
+            DeviceChoice::parse("10de:gpu"),
+            DeviceChoice::Name("10de:gpu".into())
+        );
+    }
+
+    #[test]
+    fn choice_matches() {
+        let gpu = properties("NVIDIA GeForce GTX 1080", 0x10de, 0x1b80);
+        assert!(DeviceChoice::parse("geforce").matches(0, &gpu));
+        assert!(!DeviceChoice::parse("radeon").matches(0, &gpu));
+        assert!(DeviceChoice::parse("0x10de").matches(0, &gpu));
+        assert!(DeviceChoice::parse("10de:1b80").matches(0, &gpu));
+        assert!(!DeviceChoice::parse("10de:1b81").matches(0, &gpu));
+        assert!(DeviceChoice::parse("2").matches(2, &gpu));
+        assert!(!DeviceChoice::parse("2").matches(0, &gpu));
+    }
+
+    #[test]
+    fn feature_set_operations() {
+        let required = vk::PhysicalDeviceFeatures {
+            sampler_anisotropy: vk::TRUE,
+            fill_mode_non_solid: vk::TRUE,
+            ..Default::default()
+        };
+        let supported = vk::PhysicalDeviceFeatures {
+            sampler_anisotropy: vk::TRUE,
+            wide_lines: vk::TRUE,
+            ..Default::default()
+        };
+        assert_eq!(
+            missing_features(&required, &supported),
+            ["fill_mode_non_solid"]
+        );
+        assert!(missing_features(&supported, &supported).is_empty());
+
+        let union = union_features(&required, &supported);
+        assert!(missing_features(&required, &union).is_empty());
+        assert!(missing_features(&supported, &union).is_empty());
+
+        let intersection = intersect_features(&required, &supported);
+        assert_eq!(
+            missing_features(&union, &intersection),
+            ["fill_mode_non_solid", "wide_lines"]
+        );
+    }
+
+    #[test]
+    fn table_lists_candidates() {
+        let candidates = [
+            DeviceCandidate {
+                index: 0,
+                name: "llvmpipe".into(),
+                vendor_id: 0x10005,
+                device_id: 0,
+                device_type: vk::PhysicalDeviceType::CPU,
+                rejection: Some("CPU devices are not allowed".into()),
+            },
+            DeviceCandidate {
+                index: 1,
+                name: "GeForce".into(),
+                vendor_id: 0x10de,
+                device_id: 0x1b80,
+                device_type: vk::PhysicalDeviceType::DISCRETE_GPU,
+                rejection: None,
+            },
+        ];
+        let table = candidate_table(&candidates);
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("  [0] llvmpipe (10005:0000, "));
+        assert!(lines[0].ends_with("): CPU devices are not allowed"));
+        assert!(lines[1].starts_with("  [1] GeForce (10de:1b80, "));
+        assert!(lines[1].ends_with("): OK"));
+    }
 }
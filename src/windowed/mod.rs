@@ -1,3 +1,4 @@
+use crate::debug::DebugMessages;
 use crate::*;
 use anyhow::Result;
 use erupt::{
@@ -6,24 +7,23 @@ use erupt::{
         khr_swapchain,
     },
     utils::surface,
-    DeviceLoader, EntryLoader, InstanceLoader,
+    EntryLoader,
 };
-use std::sync::Mutex;
-use std::ffi::CString;
 pub mod hardware;
+pub mod swapchain;
 use hardware::SurfaceInfo;
+use std::sync::Arc;
 use winit::window::Window;
 
+/// Preferred swapchain format; see `hardware::SurfacePreferences`
 pub const COLOR_FORMAT: vk::Format = vk::Format::B8G8R8A8_SRGB;
 pub const COLOR_SPACE: ColorSpaceKHR = ColorSpaceKHR::SRGB_NONLINEAR_KHR;
 
 /// Add extensions to `setup` needed to accomodate `window`
 pub fn extensions(setup: &mut VulkanSetup, window: &Window) -> Result<()> {
-    setup.instance_extensions.extend(
-        surface::enumerate_required_extensions(window)
-            .result()?
-            .into_iter(),
-    );
+    setup
+        .instance_extensions
+        .extend(surface::enumerate_required_extensions(window).result()?);
     setup
         .device_extensions
         .push(khr_swapchain::KHR_SWAPCHAIN_EXTENSION_NAME);
@@ -48,69 +48,63 @@ pub fn basics(
     // Entry
     let entry = EntryLoader::new()?;
 
-    // Instance
-    let application_name = CString::new(app_info.name.clone())?;
-    let engine_name = CString::new(crate::ENGINE_NAME)?;
-    let app_info = vk::ApplicationInfoBuilder::new()
-        .application_name(&application_name)
-        .application_version(vk::make_version(1, 0, 0))
-        .engine_name(&engine_name)
-        .engine_version(crate::engine_version())
-        .api_version(vk::make_version(1, 0, 0));
-
     // Gather needed extensions
     extensions(setup, window)?;
 
-    // Instance creation
-    let create_info = vk::InstanceCreateInfoBuilder::new()
-        .application_info(&app_info)
-        .enabled_extension_names(&setup.instance_extensions)
-        .enabled_layer_names(&setup.instance_layers);
-
-    let mut instance = InstanceLoader::new(&entry, &create_info, None)?;
+    // Instance
+    let debug_messages = Arc::new(DebugMessages::new(setup.panic_on_validation_error));
+    let instance = crate::core::create_instance(&entry, app_info, setup, &debug_messages)?;
+    let debug_messenger =
+        match crate::debug::create_debug_messenger(&instance, setup, &debug_messages) {
+            Ok(debug_messenger) => debug_messenger,
+            Err(e) => {
+                unsafe { crate::core::destroy_instance(&instance, None) };
+                return Err(e);
+            }
+        };
 
     // Surface
-    let surface = unsafe { surface::create_surface(&mut instance, window, None) }.result()?;
+    let surface = match unsafe { surface::create_surface(&instance, window, None) }.result() {
+        Ok(surface) => surface,
+        Err(e) => {
+            unsafe { crate::core::destroy_instance(&instance, debug_messenger) };
+            return Err(e.into());
+        }
+    };
 
     // Hardware selection
-    let (hardware, surface_info) = hardware::query(&instance, surface, &setup.device_extensions)?;
-
-    // Create logical device
-    let create_info = [vk::DeviceQueueCreateInfoBuilder::new()
-        .queue_family_index(hardware.graphics_queue_family)
-        .queue_priorities(&[1.0])];
-
-    let physical_device_features = vk::PhysicalDeviceFeaturesBuilder::new();
-    let create_info = vk::DeviceCreateInfoBuilder::new()
-        .queue_create_infos(&create_info)
-        .enabled_features(&physical_device_features)
-        .enabled_extension_names(&setup.device_extensions)
-        .enabled_layer_names(&setup.device_layers);
-
-    let device = DeviceLoader::new(&instance, hardware.physical_device, &create_info, None)?;
-
-    // Create queues
-    let graphics_queue =
-        unsafe { device.get_device_queue(hardware.graphics_queue_family, 0, None) };
-    let utility_queue = unsafe { device.get_device_queue(hardware.utility_queue_family, 0, None) };
-
-    // Create allocator
-    let device_props =
-        unsafe { gpu_alloc_erupt::device_properties(&instance, hardware.physical_device)? };
-    let allocator = Mutex::new(gpu_alloc::GpuAllocator::new(
-        gpu_alloc::Config::i_am_prototyping(),
-        device_props,
-    ));
-
-    // Create Core
-    let core = SharedCore::new(Core {
-        utility_queue,
-        graphics_queue,
-        device,
+    let selector = setup
+        .device_selector
+        .clone()
+        .require_features(setup.required_features);
+    let selection = hardware::query(
+        &instance,
+        surface,
+        &setup.device_extensions,
+        &selector,
+        &setup.surface_preferences,
+    );
+    let (hardware, surface_info) = match selection {
+        Ok(selection) => selection,
+        Err(e) => {
+            unsafe {
+                instance.destroy_surface_khr(Some(surface), None);
+                crate::core::destroy_instance(&instance, debug_messenger);
+            }
+            return Err(e);
+        }
+    };
+
+    // Create Core, which destroys the surface and instance if it fails
+    let core = Core::new_with_cleanup(
+        entry,
         instance,
-        allocator,
-        _entry: entry,
-    });
+        debug_messenger,
+        debug_messages,
+        &hardware,
+        setup,
+        |instance| unsafe { instance.destroy_surface_khr(Some(surface), None) },
+    )?;
 
     Ok((surface, hardware, surface_info, core))
 }
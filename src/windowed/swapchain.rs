@@ -0,0 +1,145 @@
+use super::hardware::SurfaceInfo;
+use crate::*;
+use anyhow::Result;
+use erupt::extensions::{
+    khr_surface::{self, SurfaceKHR},
+    khr_swapchain::{self, SwapchainKHR},
+};
+
+/// Select the swapchain extent; the surface's current extent if it has one, otherwise
+/// `window_size` clamped to what the surface supports
+pub fn select_extent(
+    surface_caps: &khr_surface::SurfaceCapabilitiesKHR,
+    window_size: vk::Extent2D,
+) -> vk::Extent2D {
+    if surface_caps.current_extent.width != u32::MAX {
+        return surface_caps.current_extent;
+    }
+    let (min, max) = (surface_caps.min_image_extent, surface_caps.max_image_extent);
+    vk::Extent2D {
+        width: window_size.width.clamp(min.width, max.width),
+        height: window_size.height.clamp(min.height, max.height),
+    }
+}
+
+/// Query the current capabilities of `surface`
+pub fn surface_caps(
+    core: &Core,
+    hardware: &HardwareSelection,
+    surface: SurfaceKHR,
+) -> Result<khr_surface::SurfaceCapabilitiesKHR> {
+    Ok(unsafe {
+        core.instance.get_physical_device_surface_capabilities_khr(
+            hardware.physical_device,
+            surface,
+            None,
+        )
+    }
+    .result()?)
+}
+
+/// Create a swapchain for `surface` with the given extent (see `select_extent`). If
+/// `old_swapchain` is given, it is retired in favor of the new swapchain but must still be
+/// destroyed by the caller.
+pub fn create_swapchain(
+    core: &Core,
+    surface: SurfaceKHR,
+    surface_info: &SurfaceInfo,
+    surface_caps: &khr_surface::SurfaceCapabilitiesKHR,
+    extent: vk::Extent2D,
+    old_swapchain: Option<SwapchainKHR>,
+) -> Result<SwapchainKHR> {
+    let mut create_info = khr_swapchain::SwapchainCreateInfoKHRBuilder::new()
+        .surface(surface)
+        .min_image_count(super::image_count(*surface_caps))
+        .image_format(surface_info.format.format)
+        .image_color_space(surface_info.format.color_space)
+        .image_extent(extent)
+        .image_array_layers(1)
+        .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT)
+        .image_sharing_mode(vk::SharingMode::EXCLUSIVE)
+        .pre_transform(surface_caps.current_transform)
+        .composite_alpha(khr_surface::CompositeAlphaFlagBitsKHR::OPAQUE_KHR)
+        .present_mode(surface_info.present_mode)
+        .clipped(true);
+
+    if let Some(old_swapchain) = old_swapchain {
+        create_info = create_info.old_swapchain(old_swapchain);
+    }
+
+    Ok(unsafe { core.device.create_swapchain_khr(&create_info, None, None) }.result()?)
+}
+
+impl SwapchainImage {
+    /// Create a view and framebuffer for a single image belonging to a swapchain
+    pub fn new(
+        core: &SharedCore,
+        image: vk::Image,
+        format: vk::Format,
+        render_pass: vk::RenderPass,
+        depth_view: vk::ImageView,
+        extent: vk::Extent2D,
+    ) -> Result<Self> {
+        let create_info = vk::ImageViewCreateInfoBuilder::new()
+            .image(image)
+            .view_type(vk::ImageViewType::_2D)
+            .format(format)
+            .subresource_range(
+                vk::ImageSubresourceRangeBuilder::new()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .base_mip_level(0)
+                    .level_count(1)
+                    .base_array_layer(0)
+                    .layer_count(1)
+                    .build(),
+            );
+        let view = unsafe { core.device.create_image_view(&create_info, None, None) }.result()?;
+        let framebuffer =
+            crate::default_engine::create_framebuffer(core, render_pass, view, depth_view, extent)?;
+
+        Ok(Self {
+            view,
+            framebuffer,
+            extent,
+            fence: vk::Fence::null(),
+            _core: core.clone(),
+        })
+    }
+}
+
+impl Drop for SwapchainImage {
+    /// Destroy the view and framebuffer. The image itself belongs to the swapchain.
+    fn drop(&mut self) {
+        unsafe {
+            self._core
+                .device
+                .destroy_framebuffer(Some(self.framebuffer), None);
+            self._core.device.destroy_image_view(Some(self.view), None);
+        }
+    }
+}
+
+/// Create a `SwapchainImage` for every image in `swapchain`
+pub fn create_swapchain_images(
+    core: &SharedCore,
+    swapchain: SwapchainKHR,
+    format: vk::Format,
+    render_pass: vk::RenderPass,
+    depth_view: vk::ImageView,
+    extent: vk::Extent2D,
+) -> Result<Vec<SwapchainImage>> {
+    unsafe { core.device.get_swapchain_images_khr(swapchain, None) }
+        .result()?
+        .into_iter()
+        .enumerate()
+        .map(|(i, image)| {
+            let swapchain_image =
+                SwapchainImage::new(core, image, format, render_pass, depth_view, extent)?;
+            let name = format!("Swapchain image {}", i);
+            core.set_name(image, &name)?;
+            core.set_name(swapchain_image.view, &name)?;
+            core.set_name(swapchain_image.framebuffer, &name)?;
+            Ok(swapchain_image)
+        })
+        .collect()
+}